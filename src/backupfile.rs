@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::Path;
+
+/// 备份方式，对应 -V/--version-control 和 VERSION_CONTROL 环境变量
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupType {
+    /// none, off：不备份
+    None,
+    /// simple, never：FILE.orig
+    Simple,
+    /// numbered, t：FILE.~N~
+    Numbered,
+    /// existing, nil：已经有编号备份时用编号备份，否则用简单备份
+    Existing,
+}
+
+impl BackupType {
+    /// 解析备份方式的名字，空字符串按默认的 existing 处理
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" | "off" => Some(BackupType::None),
+            "simple" | "never" => Some(BackupType::Simple),
+            "numbered" | "t" => Some(BackupType::Numbered),
+            "existing" | "nil" | "" => Some(BackupType::Existing),
+            _ => None,
+        }
+    }
+}
+
+/// 备份文件名的组成部分
+#[derive(Debug, Clone, Default)]
+pub struct BackupNaming {
+    /// -B PREFIX：加在整个路径前面
+    pub prefix: Option<String>,
+    /// -Y PREFIX：加在文件名（basename）前面
+    pub basename_prefix: Option<String>,
+    /// -z SUFFIX 或 SIMPLE_BACKUP_SUFFIX，简单备份的后缀
+    pub suffix: Option<String>,
+}
+
+/// 简单备份的默认后缀
+const DEFAULT_SUFFIX: &str = ".orig";
+
+/// 计算 file 的备份文件名
+///
+/// 指定了 -B 或 -Y 时使用前缀方式：PREFIX + 目录 + BASENAME_PREFIX + 文件名，
+/// 如果同时给了 -z 再加上后缀；否则按备份方式生成 FILE.orig 或 FILE.~N~。
+pub fn find_backup_file_name(file: &str, backup_type: BackupType, naming: &BackupNaming) -> String {
+    let suffix = naming.suffix.as_deref();
+
+    if naming.prefix.is_some() || naming.basename_prefix.is_some() {
+        let (dir, base) = match file.rfind('/') {
+            Some(pos) => file.split_at(pos + 1),
+            None => ("", file),
+        };
+        return format!(
+            "{}{}{}{}{}",
+            naming.prefix.as_deref().unwrap_or(""),
+            dir,
+            naming.basename_prefix.as_deref().unwrap_or(""),
+            base,
+            suffix.unwrap_or(""),
+        );
+    }
+
+    let simple = || format!("{}{}", file, suffix.unwrap_or(DEFAULT_SUFFIX));
+    match backup_type {
+        BackupType::Simple | BackupType::None => simple(),
+        BackupType::Numbered => format!("{}.~{}~", file, highest_version(file) + 1),
+        BackupType::Existing => match highest_version(file) {
+            0 => simple(),
+            n => format!("{}.~{}~", file, n + 1),
+        },
+    }
+}
+
+/// 查找按同样的设置为 file 生成过的备份文件，不存在时返回 None
+///
+/// 前缀方式和简单备份的文件名是固定的；编号备份取编号最大的那个，
+/// 也就是最近一次生成的备份。
+pub fn find_existing_backup(file: &str, backup_type: BackupType, naming: &BackupNaming) -> Option<String> {
+    let name = match (backup_type, highest_version(file)) {
+        _ if naming.prefix.is_some() || naming.basename_prefix.is_some() => {
+            find_backup_file_name(file, backup_type, naming)
+        }
+        (BackupType::Numbered | BackupType::Existing, n) if n > 0 => format!("{}.~{}~", file, n),
+        (BackupType::Numbered, _) => return None,
+        _ => format!("{}{}", file, naming.suffix.as_deref().unwrap_or(DEFAULT_SUFFIX)),
+    };
+    Path::new(&name).exists().then_some(name)
+}
+
+/// 找出 file 已有的编号备份 FILE.~N~ 中最大的 N，没有时返回 0
+fn highest_version(file: &str) -> u32 {
+    let path = Path::new(file);
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let Some(base) = path.file_name().and_then(|b| b.to_str()) else {
+        return 0;
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let version = name.strip_prefix(base)?.strip_prefix(".~")?.strip_suffix('~')?;
+            if version.is_empty() || !version.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            version.parse::<u32>().ok()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple_and_prefix_names() {
+        let mut naming = BackupNaming::default();
+        assert_eq!(find_backup_file_name("src/a.c", BackupType::Simple, &naming), "src/a.c.orig");
+
+        naming.suffix = Some("~".to_string());
+        assert_eq!(find_backup_file_name("src/a.c", BackupType::Simple, &naming), "src/a.c~");
+
+        naming.prefix = Some("/tmp/bak/".to_string());
+        naming.basename_prefix = Some("old-".to_string());
+        assert_eq!(find_backup_file_name("src/a.c", BackupType::Simple, &naming), "/tmp/bak/src/old-a.c~");
+
+        naming.suffix = None;
+        assert_eq!(find_backup_file_name("a.c", BackupType::Numbered, &naming), "/tmp/bak/old-a.c");
+    }
+
+    #[test]
+    fn test_numbered_names() {
+        let dir = std::env::temp_dir().join(format!("patch-backup-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("f.txt");
+        let file = file.to_str().unwrap();
+        let naming = BackupNaming::default();
+
+        assert_eq!(find_backup_file_name(file, BackupType::Existing, &naming), format!("{}.orig", file));
+        assert_eq!(find_backup_file_name(file, BackupType::Numbered, &naming), format!("{}.~1~", file));
+        assert_eq!(find_existing_backup(file, BackupType::Existing, &naming), None);
+        fs::write(format!("{}.~7~", file), b"").unwrap();
+        assert_eq!(find_backup_file_name(file, BackupType::Existing, &naming), format!("{}.~8~", file));
+        assert_eq!(find_existing_backup(file, BackupType::Numbered, &naming), Some(format!("{}.~7~", file)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
\ No newline at end of file
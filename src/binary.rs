@@ -0,0 +1,238 @@
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::i18n::tr;
+use crate::util::strip_cr;
+
+/// git 二进制补丁中 hunk 的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryKind {
+    /// 新文件的完整内容
+    Literal,
+    /// 相对旧文件的 delta（复制和插入指令）
+    Delta,
+}
+
+/// 二进制补丁中的一段（literal 或 delta），data 为解压后的内容
+#[derive(Debug, Clone)]
+pub struct BinaryHunk {
+    pub kind: BinaryKind,
+    pub data: Vec<u8>,
+}
+
+/// "GIT binary patch" 之后的内容：正向的一段，以及 git 为反向应用附上的第二段
+#[derive(Debug, Clone)]
+pub struct BinaryPatch {
+    pub forward: BinaryHunk,
+    /// 把新文件还原为旧文件的一段（-R 时使用），补丁中没有时为 None
+    pub reverse: Option<BinaryHunk>,
+}
+
+/// git 的 base85 字母表
+const BASE85: &[u8; 85] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// 解析 "GIT binary patch" 下一行（第 start 行）开始的二进制补丁，返回(patch, consumed_lines)
+///
+/// 每一段由 "literal N" 或 "delta N" 开头，后面是 base85 编码的 zlib 数据，
+/// 以空行结束；N 为解压后的长度。数据有误时整个补丁按损坏处理。
+pub fn parse_binary_patch(lines: &[Vec<u8>], start: usize) -> Result<(BinaryPatch, usize), String> {
+    let mut i = start;
+    let forward = parse_hunk(lines, &mut i)?
+        .ok_or_else(|| tr!("corrupt binary patch at line {}", start + 1))?;
+    let reverse = parse_hunk(lines, &mut i)?;
+    Ok((BinaryPatch { forward, reverse }, i - start))
+}
+
+/// 解析第 *i 行开始的一段，不是 "literal N" 或 "delta N" 时返回 None
+fn parse_hunk(lines: &[Vec<u8>], i: &mut usize) -> Result<Option<BinaryHunk>, String> {
+    let Some(line) = lines.get(*i) else {
+        return Ok(None);
+    };
+    let line = String::from_utf8_lossy(strip_cr(line));
+    let (kind, size) = if let Some(size) = line.strip_prefix("literal ") {
+        (BinaryKind::Literal, size)
+    } else if let Some(size) = line.strip_prefix("delta ") {
+        (BinaryKind::Delta, size)
+    } else {
+        return Ok(None);
+    };
+    let header_line = *i + 1;
+    let corrupt = |n: usize| tr!("corrupt binary patch at line {}", n);
+    let size: usize = size.trim().parse().map_err(|_| corrupt(header_line))?;
+    *i += 1;
+
+    let mut deflated = Vec::new();
+    while let Some(line) = lines.get(*i).map(|l| strip_cr(l)) {
+        if line.is_empty() {
+            *i += 1;
+            break;
+        }
+        deflated.extend(decode_base85_line(line).ok_or_else(|| corrupt(*i + 1))?);
+        *i += 1;
+    }
+    // 多解出一个字节，以便发现比声明的长度更长的数据
+    let data = inflate(&deflated, size + 1).ok_or_else(|| corrupt(header_line))?;
+    if data.len() != size {
+        return Err(corrupt(header_line));
+    }
+    Ok(Some(BinaryHunk { kind, data }))
+}
+
+/// 解码一行 base85 数据：第一个字符表示字节数（A-Z 为 1-26，a-z 为 27-52），
+/// 后面每 5 个字符表示 4 个字节
+fn decode_base85_line(line: &[u8]) -> Option<Vec<u8>> {
+    let (&len, encoded) = line.split_first()?;
+    let len = match len {
+        b'A'..=b'Z' => usize::from(len - b'A') + 1,
+        b'a'..=b'z' => usize::from(len - b'a') + 27,
+        _ => return None,
+    };
+    if encoded.len() != len.div_ceil(4) * 5 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(len + 3);
+    for group in encoded.chunks(5) {
+        let mut acc: u32 = 0;
+        for &c in group {
+            let digit = BASE85.iter().position(|&b| b == c)? as u32;
+            acc = acc.checked_mul(85)?.checked_add(digit)?;
+        }
+        out.extend_from_slice(&acc.to_be_bytes());
+    }
+    out.truncate(len);
+    Some(out)
+}
+
+impl BinaryPatch {
+    /// -R：交换正向和反向的两段，没有反向的一段时返回 false
+    pub fn reverse(&mut self) -> bool {
+        let Some(reverse) = self.reverse.take() else {
+            return false;
+        };
+        self.reverse = Some(std::mem::replace(&mut self.forward, reverse));
+        true
+    }
+
+    /// 把补丁应用到 input 上，返回新文件的内容
+    ///
+    /// literal 直接给出新内容；delta 与 input 不符时失败。input 和结果是否与
+    /// index 行中的 hash 一致由调用者核对。
+    pub fn apply(&self, input: &[u8], target: &str) -> Result<Vec<u8>, String> {
+        match self.forward.kind {
+            BinaryKind::Literal => Ok(self.forward.data.clone()),
+            BinaryKind::Delta => apply_delta(input, &self.forward.data)
+                .ok_or_else(|| tr!("binary patch does not apply to {}", target)),
+        }
+    }
+}
+
+/// 按 git 的 delta 格式由 src 生成新内容，delta 与 src 不符时返回 None
+///
+/// delta 开头是变长编码的旧文件长度和新文件长度，后面是指令：最高位为 1 时
+/// 从 src 复制（低 4 位表示 offset 的哪几个字节存在，接下来 3 位表示 size 的），
+/// 否则把后面的若干字节（1-127）直接插入。
+fn apply_delta(src: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    let mut varint = || {
+        let mut value = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(pos)?;
+            pos += 1;
+            value |= usize::from(byte & 0x7f).checked_shl(shift)?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    };
+    let src_size = varint()?;
+    let dst_size = varint()?;
+    if src_size != src.len() {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(dst_size);
+    while let Some(&cmd) = delta.get(pos) {
+        pos += 1;
+        if cmd & 0x80 != 0 {
+            // 按位取出 offset（4 字节）和 size（3 字节）中存在的字节，低位在前
+            let mut field = |bits: std::ops::Range<u32>| -> Option<usize> {
+                let mut value = 0usize;
+                for (n, bit) in bits.enumerate() {
+                    if cmd & (1 << bit) != 0 {
+                        value |= usize::from(*delta.get(pos)?) << (8 * n);
+                        pos += 1;
+                    }
+                }
+                Some(value)
+            };
+            let offset = field(0..4)?;
+            let size = match field(4..7)? {
+                0 => 0x10000,
+                size => size,
+            };
+            out.extend_from_slice(src.get(offset..offset.checked_add(size)?)?);
+        } else if cmd != 0 {
+            let end = pos + usize::from(cmd);
+            out.extend_from_slice(delta.get(pos..end)?);
+            pos = end;
+        } else {
+            return None;
+        }
+    }
+    (out.len() == dst_size).then_some(out)
+}
+
+/// 解压 zlib 数据，最多解出 limit 字节；数据损坏或校验和不符时返回 None
+fn inflate(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(data).take(limit as u64).read_to_end(&mut out).ok()?;
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<Vec<u8>> {
+        text.lines().map(|l| l.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_literal_sections() {
+        let patch = lines("literal 6\nNcmezWk0CF$8~_c014sY>\n\nliteral 0\nHcmV?d00001\n\ndiff --git a/x b/x\n");
+        let (binary, consumed) = parse_binary_patch(&patch, 0).unwrap();
+        assert_eq!(consumed, 6);
+        assert_eq!(binary.forward.kind, BinaryKind::Literal);
+        assert_eq!(binary.forward.data, b"\xff\xfe\x00new");
+        assert_eq!(binary.reverse.map(|r| r.data), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_delta_sections() {
+        let patch = lines("delta 21\nVcmZqRXyBNT!W97r8}lYH0{}~i2QvTw\n\ndelta 10\nRcmZqRXy91H$h?S=5daTR0$Tt8\n");
+        let (binary, consumed) = parse_binary_patch(&patch, 0).unwrap();
+        assert_eq!(consumed, 5);
+        let old: Vec<u8> = (0..4).flat_map(|_| 0..=255u8).collect();
+        let mut new = old.clone();
+        new[100..110].fill(b'X');
+        assert_eq!(apply_delta(&old, &binary.forward.data).as_deref(), Some(&new[..]));
+        assert_eq!(apply_delta(&old[1..], &binary.forward.data), None);
+
+        // -R 时用第二段把新文件还原
+        let mut binary = binary;
+        assert!(binary.reverse());
+        assert_eq!(binary.apply(&new, "x").as_deref(), Ok(&old[..]));
+    }
+
+    #[test]
+    fn test_corrupt_sections() {
+        // 长度字符与数据不符、base85 字符非法、解压后的长度不符
+        assert!(parse_binary_patch(&lines("literal 6\nMcmezWk0CF$8~_c014sY>\n"), 0).is_err());
+        assert!(parse_binary_patch(&lines("literal 6\nNcmezWk0CF$8~_c014sY\"\n"), 0).is_err());
+        assert!(parse_binary_patch(&lines("literal 7\nNcmezWk0CF$8~_c014sY>\n"), 0).is_err());
+        assert!(parse_binary_patch(&lines("GIT binary patch\n"), 0).is_err());
+    }
+}
\ No newline at end of file
@@ -1,21 +1,15 @@
-use std::fs::File;
-use std::sync::atomic::{AtomicBool, AtomicI32};
+use crate::backupfile::BackupType;
+use crate::encoding::{Charset, Encoding};
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize};
 
 pub type Idx = usize;
 
-#[derive(Debug, Clone)]
-pub struct OutFile {
-    pub name: String,
-    pub exists: Option<String>,
-    pub alloc: Option<String>,
-    pub temporary: bool,
-}
-
-pub static DEBUG: AtomicBool = AtomicBool::new(true);
-
-pub static FORCE: AtomicBool = AtomicBool::new(false);
-pub static BATCH: AtomicBool = AtomicBool::new(false);
+/// 调试跟踪，由环境变量 PATCH_DEBUG 打开
+pub static DEBUG: AtomicBool = AtomicBool::new(false);
 
+// 与 GNU patch 中补丁格式的名称一一对应
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DiffType {
     NoDiff,
@@ -29,16 +23,122 @@ pub enum DiffType {
 
 pub static DIFF_TYPE: AtomicI32 = AtomicI32::new(DiffType::NoDiff as i32);
 
+/// --dry-run：照常解析和匹配并输出报告，但不创建、修改、删除或备份任何文件
 pub static DRY_RUN: AtomicBool = AtomicBool::new(false);
 
+/// --binary：不做任何行尾转换，按原始字节比较和写入
+pub static BINARY: AtomicBool = AtomicBool::new(false);
+
+/// -D NAME：用 #ifdef/#ifndef 包裹改动时使用的宏名
+pub static IFDEF_NAME: OnceLock<String> = OnceLock::new();
+
+/// -p NUM：去掉文件名开头的路径组件数，-1 表示只保留文件名本身
+pub static STRIP: AtomicI32 = AtomicI32::new(-1);
+
+/// -i PATCHFILE：补丁文件
+pub static PATCH_FILE: OnceLock<String> = OnceLock::new();
+
+/// -b：修补前总是备份原文件
+pub static BACKUP: AtomicBool = AtomicBool::new(false);
+/// --backup-if-mismatch：补丁不完全匹配时备份原文件，未设置 POSIXLY_CORRECT 时默认开启
+pub static BACKUP_IF_MISMATCH: AtomicBool = AtomicBool::new(true);
+/// -V STYLE：备份方式，未指定时取 PATCH_VERSION_CONTROL 或 VERSION_CONTROL 环境变量
+pub static BACKUP_TYPE: OnceLock<BackupType> = OnceLock::new();
+/// -B PREFIX
+pub static BACKUP_PREFIX: OnceLock<String> = OnceLock::new();
+/// -Y PREFIX
+pub static BACKUP_BASENAME_PREFIX: OnceLock<String> = OnceLock::new();
+/// -z SUFFIX，未指定时取 SIMPLE_BACKUP_SUFFIX 环境变量
+pub static BACKUP_SUFFIX: OnceLock<String> = OnceLock::new();
+
+/// --restore：不应用补丁，而是从备份恢复补丁涉及的文件
+pub static RESTORE: AtomicBool = AtomicBool::new(false);
+
+/// --atomic：所有文件都成功应用后才替换目标文件，否则不修改任何文件
+pub static ATOMIC: AtomicBool = AtomicBool::new(false);
+
+/// --check：只检查补丁能否应用，不修改任何文件
+pub static CHECK: AtomicBool = AtomicBool::new(false);
+/// --stat：输出 diffstat
+pub static STAT: AtomicBool = AtomicBool::new(false);
+/// --numstat：输出以制表符分隔的统计
+pub static NUMSTAT: AtomicBool = AtomicBool::new(false);
+/// --summary：列出新建、删除和权限变化的文件
+pub static SUMMARY: AtomicBool = AtomicBool::new(false);
+
+/// --merge：找不到应用位置的 hunk 写入冲突标记，而不是 .rej 文件
+pub static MERGE: AtomicBool = AtomicBool::new(false);
+
+/// --format=json：以 JSON 输出应用结果，不输出普通的文字报告
+pub static JSON: AtomicBool = AtomicBool::new(false);
+
+/// -E：删除修补后为空的文件
+pub static REMOVE_EMPTY_FILES: AtomicBool = AtomicBool::new(false);
+
+/// -F NUM：模糊匹配时最多忽略的首尾上下文行数
+pub static FUZZ: AtomicUsize = AtomicUsize::new(2);
+
+/// -l：比较时忽略空白的差异
+pub static IGNORE_WHITESPACE: AtomicBool = AtomicBool::new(false);
+
+/// -R：交换补丁的新旧两边，反向应用
+pub static REVERSE: AtomicBool = AtomicBool::new(false);
+
+/// --strict：拒绝需要偏移、模糊匹配或忽略空白才能应用的 hunk
+pub static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// 退出状态，与 GNU patch 相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExitStatus {
+    /// 所有 hunk 都成功应用
+    Success = 0,
+    /// 有 hunk 应用失败
+    Failed = 1,
+    /// 严重错误：参数错误、补丁无法解析、文件无法读写等
+    Trouble = 2,
+}
+
+impl ExitStatus {
+    /// 取两个状态中更严重的一个
+    pub fn worst(self, other: ExitStatus) -> ExitStatus {
+        self.max(other)
+    }
+
+    /// 以该状态退出进程
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+/// 输出的详细程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Verbosity {
     Default,
+    /// -s/--quiet：只输出错误
     Silent,
+    /// --verbose：额外输出补丁格式的判断和查找 hunk 位置的过程
     Verbose,
 }
 
+pub static VERBOSITY: AtomicI32 = AtomicI32::new(Verbosity::Default as i32);
+
+/// --encoding=[FILE=]ENC：强制指定目标文件的字符集，不带 FILE 时作用于所有文件
+pub static FORCED_ENCODINGS: Mutex<Vec<(Option<String>, Charset)>> = Mutex::new(Vec::new());
+
+/// 文件的行尾约定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
 pub struct OutState {
-    pub file: File,
+    /// 输出的内容，由调用者写入临时文件（--dry-run 时不写入）
+    pub out: Vec<u8>,
     pub after_newline: bool,
     pub zero_output: bool,
+    /// 新增行使用的行尾约定，与目标文件保持一致
+    pub line_ending: LineEnding,
+    /// 输出时重新编码成目标文件原来的编码
+    pub encoding: Encoding,
 }
\ No newline at end of file
@@ -0,0 +1,307 @@
+use crate::i18n::tr;
+use crate::inp::InputFile;
+use crate::pch::{HunkLine, LineKind, PatchHunk};
+
+/// ed 命令类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdOp {
+    Append,   // a
+    Insert,   // i
+    Change,   // c
+    Delete,   // d
+    Unescape, // s/.//，diff -e 用它还原单独一个 "." 的行
+}
+
+/// ed 脚本中的一条命令
+#[derive(Debug, Clone)]
+pub struct EdCommand {
+    pub op: EdOp,
+    /// 命令地址 (起始行, 结束行)，None 表示当前行
+    pub range: Option<(usize, usize)>,
+    pub text: Vec<Vec<u8>>,
+    /// 命令在补丁文件中的行号（1-based）
+    pub patch_line: usize,
+}
+
+/// 判断一行是否是 diff -e 生成的 ed 命令行（如 "3a"、"2,4d"、"5c"）
+pub fn is_ed_command(line: &str) -> bool {
+    ['a', 'c', 'd', 'i'].into_iter().any(|op| {
+        line.strip_suffix(op)
+            .is_some_and(|addr| parse_address(addr).is_some_and(|a| a.is_some()))
+    })
+}
+
+/// 解析 "N" 或 "N,M" 形式的地址；空地址返回 Some(None)
+fn parse_address(addr: &str) -> Option<Option<(usize, usize)>> {
+    if addr.is_empty() {
+        return Some(None);
+    }
+    let (start, end) = match addr.split_once(',') {
+        Some((s, e)) => (s, e),
+        None => (addr, addr),
+    };
+    if start.is_empty() || end.is_empty() {
+        return None;
+    }
+    if !start.bytes().all(|b| b.is_ascii_digit()) || !end.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(Some((start.parse().ok()?, end.parse().ok()?)))
+}
+
+/// 从行向量解析 ed 脚本，返回(命令列表, consumed_lines)
+///
+/// 只接受 diff -e 会用到的 a/i/c/d 和 s/.//，以及结尾的 w/q；
+/// 任何 shell 命令（!）、写入其他文件或读取文件的命令都会被拒绝。
+pub fn parse_ed_script(lines: &[Vec<u8>], start_idx: usize) -> Result<(Vec<EdCommand>, usize), String> {
+    let mut commands = Vec::new();
+    let mut i = start_idx;
+
+    while i < lines.len() {
+        let line = String::from_utf8_lossy(&lines[i]);
+        let line = line.trim_end();
+
+        if line == "s/.//" {
+            commands.push(EdCommand { op: EdOp::Unescape, range: None, text: Vec::new(), patch_line: i + 1 });
+            i += 1;
+            continue;
+        }
+        if line == "w" || line == "q" || line == "wq" {
+            i += 1;
+            if line == "w" {
+                continue;
+            }
+            break;
+        }
+        let addr_len = line
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, ',' | '$' | '.')))
+            .unwrap_or(line.len());
+        let (addr, rest) = line.split_at(addr_len);
+        if is_unsafe_command(rest) {
+            return Err(tr!("ed script line {}: refusing to run unsafe command: {}", i + 1, line));
+        }
+
+        let op = match rest {
+            "a" => EdOp::Append,
+            "i" => EdOp::Insert,
+            "c" => EdOp::Change,
+            "d" => EdOp::Delete,
+            // 不是 ed 命令，脚本到此结束
+            _ if addr.is_empty() || !rest.starts_with(|c: char| c.is_ascii_alphabetic()) => break,
+            _ => return Err(tr!("ed script line {}: unsupported command: {}", i + 1, line)),
+        };
+        let Some(range) = parse_address(addr) else {
+            return Err(tr!("ed script line {}: unsupported address: {}", i + 1, line));
+        };
+        let patch_line = i + 1;
+        i += 1;
+
+        let mut text = Vec::new();
+        if op != EdOp::Delete {
+            loop {
+                let Some(t) = lines.get(i) else {
+                    return Err(tr!("unexpected end of ed script: command at line {} has no terminating \".\" line", patch_line));
+                };
+                i += 1;
+                if crate::util::strip_cr(t) == b"." {
+                    break;
+                }
+                text.push(t.clone());
+            }
+        }
+        commands.push(EdCommand { op, range, text, patch_line });
+    }
+
+    Ok((commands, i - start_idx))
+}
+
+/// 会执行 shell、读写其他文件的 ed 命令
+fn is_unsafe_command(cmd: &str) -> bool {
+    if cmd.starts_with('!') {
+        return true;
+    }
+    let mut chars = cmd.chars();
+    matches!(chars.next(), Some('w' | 'W' | 'r' | 'e' | 'E' | 'f'))
+        && chars.next().is_none_or(|c| c == ' ' || c == '!')
+        && cmd != "w"
+}
+
+/// ed 缓冲区中的一行：原文件的第 N 行，或者脚本插入的文本（及插入它的命令在补丁中的行号）
+#[derive(Debug, Clone)]
+enum EdLine {
+    Orig(usize),
+    New(Vec<u8>, usize),
+}
+
+/// 在内存中执行 ed 脚本，并把结果转换成不带上下文的 hunk 列表
+///
+/// 脚本只作用于输入文件的副本，不会调用外部的 ed 程序。每个 hunk 在补丁文件中的
+/// 位置记为产生这段改动的命令中，在脚本里最靠前的一条。
+pub fn ed_hunks(commands: &[EdCommand], input: &InputFile) -> Result<Vec<PatchHunk>, String> {
+    let mut buffer: Vec<EdLine> = (1..=input.num_lines()).map(EdLine::Orig).collect();
+    let mut dot = buffer.len();
+    // 原文件每一行被哪条命令删除（命令在补丁中的行号），0 表示没有被删除
+    let mut removed_by = vec![0; input.num_lines() + 2];
+
+    for (n, cmd) in commands.iter().enumerate() {
+        let (start, end) = cmd.range.unwrap_or((dot, dot));
+        let bad_address = || tr!("ed command #{}: address {},{} out of range", n + 1, start, end);
+        if start > end || end > buffer.len() {
+            return Err(bad_address());
+        }
+        let new_lines = cmd.text.iter().map(|t| EdLine::New(t.clone(), cmd.patch_line));
+
+        match cmd.op {
+            EdOp::Append => {
+                buffer.splice(end..end, new_lines);
+                dot = end + cmd.text.len();
+            }
+            EdOp::Insert => {
+                let at = end.saturating_sub(1);
+                buffer.splice(at..at, new_lines);
+                dot = at + cmd.text.len().max(1);
+            }
+            EdOp::Change | EdOp::Delete => {
+                if start == 0 {
+                    return Err(bad_address());
+                }
+                for line in buffer.splice(start - 1..end, new_lines) {
+                    if let EdLine::Orig(k) = line {
+                        removed_by[k] = cmd.patch_line;
+                    }
+                }
+                dot = if cmd.op == EdOp::Change && !cmd.text.is_empty() {
+                    start - 1 + cmd.text.len()
+                } else {
+                    start.min(buffer.len())
+                };
+            }
+            EdOp::Unescape => {
+                if dot == 0 || dot > buffer.len() {
+                    return Err(bad_address());
+                }
+                let (text, line) = match &buffer[dot - 1] {
+                    EdLine::New(s, line) => (s.clone(), *line),
+                    EdLine::Orig(k) => {
+                        removed_by[*k] = cmd.patch_line;
+                        (input.ifetch(*k).map(|l| l.ptr.to_vec()).unwrap_or_default(), cmd.patch_line)
+                    }
+                };
+                let Some(stripped) = text.strip_prefix(b".") else {
+                    return Err(tr!("ed command #{}: s/.// did not match", n + 1));
+                };
+                buffer[dot - 1] = EdLine::New(stripped.to_vec(), line);
+            }
+        }
+    }
+
+    // 对比执行结果和原文件，找出被删除和插入的区域
+    let mut hunks = Vec::new();
+    let mut next_orig = 1;
+    let mut new_line = 1;
+    let mut pos = 0;
+    loop {
+        if let Some(EdLine::Orig(k)) = buffer.get(pos)
+            && *k == next_orig
+        {
+            next_orig += 1;
+            new_line += 1;
+            pos += 1;
+            continue;
+        }
+
+        // 从这里开始是一段改动：删除 next_orig..upto，插入 pos..run_end 的新文本
+        let run_end = pos + buffer[pos..].iter().take_while(|l| matches!(l, EdLine::New(..))).count();
+        let upto = match buffer.get(run_end) {
+            Some(EdLine::Orig(k)) => *k,
+            _ => input.num_lines() + 1,
+        };
+
+        let mut lines = Vec::new();
+        let mut patch_line = removed_by[next_orig..upto].iter().copied().min().unwrap_or(usize::MAX);
+        for k in next_orig..upto {
+            let content = input.ifetch(k).map(|l| l.ptr.to_vec()).unwrap_or_default();
+            lines.push(HunkLine { kind: LineKind::Remove, content, missing_newline: false });
+        }
+        for line in &buffer[pos..run_end] {
+            if let EdLine::New(text, line) = line {
+                lines.push(HunkLine { kind: LineKind::Add, content: text.clone(), missing_newline: false });
+                patch_line = patch_line.min(*line);
+            }
+        }
+
+        if !lines.is_empty() {
+            let orig_count = upto - next_orig;
+            let new_count = run_end - pos;
+            hunks.push(PatchHunk {
+                orig_start: if orig_count == 0 { next_orig - 1 } else { next_orig },
+                orig_count,
+                new_start: if new_count == 0 { new_line - 1 } else { new_line },
+                new_count,
+                lines,
+                func: None,
+                patch_line,
+            });
+        }
+        new_line += run_end - pos;
+        next_orig = upto;
+        pos = run_end;
+        if pos >= buffer.len() {
+            break;
+        }
+    }
+
+    Ok(hunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(text: &str) -> Vec<Vec<u8>> {
+        text.lines().map(|l| l.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_is_ed_command() {
+        assert!(is_ed_command("3a"));
+        assert!(is_ed_command("2,4d"));
+        assert!(!is_ed_command("3c3"));
+        assert!(!is_ed_command("a"));
+        assert!(!is_ed_command("add"));
+        // 以多字节字符结尾的行
+        assert!(!is_ed_command("Subject: 修复错误"));
+    }
+
+    #[test]
+    fn test_parse_lone_dot_trick() {
+        let lines = script("3a\nfoo\n..\n.\ns/.//\na\nbar\n.\n");
+        let (cmds, consumed) = parse_ed_script(&lines, 0).unwrap();
+        assert_eq!(consumed, 8);
+        assert_eq!(cmds.len(), 3);
+        assert_eq!(cmds[1].op, EdOp::Unescape);
+        assert_eq!(cmds[2].range, None);
+    }
+
+    #[test]
+    fn test_hunk_patch_lines() {
+        // diff -e 从后往前给出命令，每个 hunk 记录产生它的命令所在的行
+        let lines = script("5d\n2c\ntwo\n.\n");
+        let (cmds, _) = parse_ed_script(&lines, 0).unwrap();
+        assert_eq!(cmds.iter().map(|c| c.patch_line).collect::<Vec<_>>(), [1, 2]);
+        let input = InputFile::from_bytes(b"1\n2\n3\n4\n5\n", "t").unwrap();
+        let hunks = ed_hunks(&cmds, &input).unwrap();
+        assert_eq!(hunks.iter().map(|h| h.patch_line).collect::<Vec<_>>(), [2, 1]);
+
+        let err = parse_ed_script(&script("1d\n3a\nfoo\n"), 0).unwrap_err();
+        assert!(err.contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_refuse_unsafe_commands() {
+        assert!(parse_ed_script(&script("1d\n!rm -rf /\n"), 0).is_err());
+        assert!(parse_ed_script(&script("1d\nw /etc/passwd\n"), 0).is_err());
+        assert!(parse_ed_script(&script("1,$r /etc/shadow\n"), 0).is_err());
+        assert!(parse_ed_script(&script("1d\nexit\n"), 0).is_ok_and(|(c, n)| c.len() == 1 && n == 1));
+    }
+}
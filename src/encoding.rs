@@ -0,0 +1,156 @@
+use std::io;
+
+use crate::common;
+use crate::i18n::tr;
+
+/// 目标文件的字符集
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// 目标文件的编码：字符集以及是否带 BOM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoding {
+    pub charset: Charset,
+    pub bom: bool,
+}
+
+impl Encoding {
+    pub const UTF8: Encoding = Encoding { charset: Charset::Utf8, bom: false };
+
+    /// 解析 --encoding 的参数
+    pub fn from_name(name: &str) -> Option<Charset> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Charset::Utf8),
+            "utf-16le" | "utf16le" | "utf-16" | "utf16" => Some(Charset::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Charset::Utf16Be),
+            _ => None,
+        }
+    }
+
+    fn bom_bytes(&self) -> &'static [u8] {
+        match self.charset {
+            Charset::Utf8 => b"\xEF\xBB\xBF",
+            Charset::Utf16Le => b"\xFF\xFE",
+            Charset::Utf16Be => b"\xFE\xFF",
+        }
+    }
+
+    /// BOM 字节，不带 BOM 时为空
+    pub fn bom(&self) -> &'static [u8] {
+        if self.bom { self.bom_bytes() } else { b"" }
+    }
+
+    /// 把 UTF-8 字节编码为本编码（不含 BOM）
+    ///
+    /// UTF-8 原样返回，保证任意字节都能无损写回；转为 UTF-16 时非法的
+    /// UTF-8 序列替换为 U+FFFD。
+    pub fn encode(&self, utf8: &[u8]) -> Vec<u8> {
+        let units = match self.charset {
+            Charset::Utf8 => return utf8.to_vec(),
+            _ => String::from_utf8_lossy(utf8).encode_utf16().collect::<Vec<u16>>(),
+        };
+        let mut out = Vec::with_capacity(units.len() * 2);
+        for unit in units {
+            match self.charset {
+                Charset::Utf16Be => out.extend_from_slice(&unit.to_be_bytes()),
+                _ => out.extend_from_slice(&unit.to_le_bytes()),
+            }
+        }
+        out
+    }
+}
+
+/// 查找 --encoding 为某个文件指定的字符集，针对具体文件的设置优先
+pub fn forced_charset(filename: &str) -> Option<Charset> {
+    let forced = common::FORCED_ENCODINGS.lock().unwrap();
+    forced.iter().rev()
+        .find(|(name, _)| name.as_deref() == Some(filename))
+        .or_else(|| forced.iter().rev().find(|(name, _)| name.is_none()))
+        .map(|(_, charset)| *charset)
+}
+
+/// 识别文件内容的编码；forced 为 --encoding 指定的字符集
+///
+/// 优先依据 BOM；没有 BOM 时，如果大部分奇数（或偶数）位置的字节都是 0，
+/// 就认为是不带 BOM 的 UTF-16。
+pub fn detect(data: &[u8], forced: Option<Charset>) -> Encoding {
+    for charset in [Charset::Utf8, Charset::Utf16Le, Charset::Utf16Be] {
+        let enc = Encoding { charset, bom: true };
+        if data.starts_with(enc.bom_bytes()) && forced.is_none_or(|f| f == charset) {
+            return enc;
+        }
+    }
+    if let Some(charset) = forced {
+        return Encoding { charset, bom: false };
+    }
+
+    if data.len() >= 2 && data.len().is_multiple_of(2) {
+        let pairs = data.len() / 2;
+        let even_zero = data.iter().step_by(2).filter(|&&b| b == 0).count();
+        let odd_zero = data.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+        let charset = if odd_zero * 5 >= pairs * 2 && even_zero == 0 {
+            Some(Charset::Utf16Le)
+        } else if even_zero * 5 >= pairs * 2 && odd_zero == 0 {
+            Some(Charset::Utf16Be)
+        } else {
+            None
+        };
+        if let Some(charset) = charset {
+            let enc = Encoding { charset, bom: false };
+            if decode(data, enc).is_ok() {
+                return enc;
+            }
+        }
+    }
+    Encoding::UTF8
+}
+
+/// 把文件内容解码为 UTF-8 字节（去掉 BOM）
+pub fn decode(data: &[u8], enc: Encoding) -> io::Result<Vec<u8>> {
+    let data = data.strip_prefix(enc.bom()).unwrap_or(data);
+    if enc.charset == Charset::Utf8 {
+        return Ok(data.to_vec());
+    }
+    if !data.len().is_multiple_of(2) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, tr!("UTF-16 file has an odd number of bytes")));
+    }
+    let units = data.chunks_exact(2).map(|c| match enc.charset {
+        Charset::Utf16Be => u16::from_be_bytes([c[0], c[1]]),
+        _ => u16::from_le_bytes([c[0], c[1]]),
+    });
+    let text: String = char::decode_utf16(units)
+        .collect::<Result<_, _>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, tr!("UTF-16 decoding failed: {}", e)))?;
+    Ok(text.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_bom() {
+        assert_eq!(detect(b"\xEF\xBB\xBFabc", None), Encoding { charset: Charset::Utf8, bom: true });
+        assert_eq!(detect(b"\xFF\xFEa\0", None), Encoding { charset: Charset::Utf16Le, bom: true });
+        assert_eq!(detect(b"abc", None), Encoding::UTF8);
+    }
+
+    #[test]
+    fn test_detect_utf16_without_bom() {
+        let data = Encoding { charset: Charset::Utf16Be, bom: false }.encode(b"line 1\nline 2\n");
+        assert_eq!(detect(&data, None), Encoding { charset: Charset::Utf16Be, bom: false });
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let enc = Encoding { charset: Charset::Utf16Le, bom: true };
+        let mut data = enc.bom().to_vec();
+        data.extend(enc.encode("中文\r\n".as_bytes()));
+        assert_eq!(detect(&data, None), enc);
+        assert_eq!(decode(&data, enc).unwrap(), "中文\r\n".as_bytes());
+    }
+}
@@ -1,5 +1,87 @@
 /// 帮助文本，保持与 GNU patch 兼容
 pub const HELP_TEXT: &str = r#"
+Usage: patch [OPTION]... [ORIGFILE [PATCHFILE]]
+
+Input options:
+
+  -p NUM  --strip=NUM         Strip NUM leading components from file names.
+  -F LINES  --fuzz=LINES      Set the fuzz factor to LINES for inexact matching.
+  -l  --ignore-whitespace     Ignore white space changes between patch and input.
+
+  -c  --context               Interpret the patch as a context difference.
+  -e  --ed                    Interpret the patch as an ed script.
+  -n  --normal                Interpret the patch as a normal difference.
+  -u  --unified               Interpret the patch as a unified difference.
+
+  -N  --forward               Ignore patches that appear to be reversed or already applied.
+  -R  --reverse               Assume patches were created with old and new files swapped.
+  -i PATCHFILE  --input=PATCHFILE  Read patch from PATCHFILE instead of stdin.
+  --strict                    Reject hunks that need an offset, fuzz or white space relaxation.
+
+Output options:
+
+  -o FILE  --output=FILE      Output patched files to FILE.
+  -r FILE  --reject-file=FILE Output rejects to FILE.
+
+  -D NAME  --ifdef=NAME       Make merged if-then-else output using NAME.
+  --merge                     Merge using conflict markers instead of creating reject files.
+  --format=FORMAT             Output format for results (text, json).
+  -E  --remove-empty-files    Remove output files that are empty after patching.
+
+  -Z  --set-utc               Set times of patched files, assuming diff uses UTC (GMT).
+  -T  --set-time              Likewise, assuming local time.
+
+  --quoting-style=WORD        Output file names using quoting style WORD.
+                             Valid WORDs are: literal, shell, c, escape.
+                             Default is taken from QUOTING_STYLE env variable, or 'shell' if unset.
+
+Backup and version control options:
+
+  -b  --backup                Back up the original contents of each file.
+  --backup-if-mismatch        Back up if the patch does not match exactly.
+  --no-backup-if-mismatch     Back up mismatches only if otherwise requested.
+
+  -V STYLE  --version-control=STYLE  Use STYLE version control (simple, numbered, existing).
+  -B PREFIX  --prefix=PREFIX         Prepend PREFIX to backup file names.
+  -Y PREFIX  --basename-prefix=PREFIX  Prepend PREFIX to backup file basenames.
+  -z SUFFIX  --suffix=SUFFIX         Append SUFFIX to backup file names.
+  --restore                   Restore the files touched by the patch from their backups.
+
+  -g NUM  --get=NUM           Get files from RCS etc. if positive; ask if negative.
+
+Miscellaneous options:
+
+  -t  --batch                 Ask no questions; skip bad-Prereq patches; assume reversed.
+  -f  --force                 Like -t, but ignore bad-Prereq patches, and assume unreversed.
+  -s  --quiet  --silent       Work silently unless an error occurs.
+  --verbose                   Output extra information about the work being done.
+  --dry-run                   Do not actually change any files; just print what would happen.
+  --atomic                    Change files only if every file applies; otherwise change none.
+  --check                     Only check whether the patch applies; do not change any files.
+  --stat                      Print a diffstat (does not change files).
+  --numstat                   Print one tab-separated line per file (does not change files):
+                             added removed applied-hunks total-hunks max-offset max-fuzz name
+  --summary                   List created, deleted and mode-changed files (does not change files).
+  --posix                     Conform to the POSIX standard.
+
+  -d DIR  --directory=DIR     Change the working directory to DIR first.
+  --reject-format=FORMAT      Create 'context' or 'unified' rejects.
+  --binary                    Read and write data in binary mode.
+  --encoding=[FILE=]ENC       Set the encoding of target files (utf-8, utf-16le, utf-16be);
+                             detected from the BOM by default.
+  --read-only=BEHAVIOR        How to handle read-only input files: 'ignore' that they
+                             are read-only, 'warn' (default), or 'fail'.
+
+  -v  --version               Output version info.
+  --help                      Output this help.
+
+Exit status is 0 if all hunks applied, 1 if some hunks failed, 2 if there was serious trouble.
+
+Report bugs to <bug-gnu-patch@gnu.org>.
+"#;
+
+/// 帮助文本的中文翻译，由 i18n 的消息目录选用
+pub const HELP_TEXT_ZH: &str = r#"
 用法: patch [选项]... [原文件 [补丁文件]]
 
 输入选项:
@@ -16,6 +98,7 @@ pub const HELP_TEXT: &str = r#"
   -N  --forward               忽略反向或已应用的补丁
   -R  --reverse               交换原文件和新文件位置
   -i PATCHFILE  --input=PATCHFILE  指定补丁文件
+  --strict                    拒绝需要偏移、模糊匹配或忽略空白才能应用的 hunk
 
 输出选项:
 
@@ -24,6 +107,7 @@ pub const HELP_TEXT: &str = r#"
 
   -D NAME  --ifdef=NAME       用 ifdef/ifndef 方式输出冲突
   --merge                     使用冲突标记而不是 reject 文件输出
+  --format=FORMAT             结果输出格式（text, json）
   -E  --remove-empty-files    删除应用后为空的文件
 
   -Z  --set-utc               用 UTC 时间设置文件时间戳
@@ -42,6 +126,7 @@ pub const HELP_TEXT: &str = r#"
   -B PREFIX  --prefix=PREFIX         备份文件名前缀
   -Y PREFIX  --basename-prefix=PREFIX  备份文件基名前缀
   -z SUFFIX  --suffix=SUFFIX         备份文件名后缀
+  --restore                   按上述备份设置从备份恢复补丁涉及的文件
 
   -g NUM  --get=NUM           RCS/SCCS 文件获取模式（正值自动，负值询问）
 
@@ -52,15 +137,25 @@ pub const HELP_TEXT: &str = r#"
   -s  --quiet --silent        安静模式，出错才显示
   --verbose                   显示详细信息
   --dry-run                   仅显示将做什么，不实际修改文件
+  --atomic                    所有文件都成功应用才修改，否则不修改任何文件
+  --check                     只检查补丁能否应用，不修改任何文件
+  --stat                      输出 diffstat（不修改文件）
+  --numstat                   每个文件输出一行制表符分隔的统计（不修改文件）：
+                             新增行 删除行 可应用hunk数 hunk总数 最大偏移 最大模糊 文件名
+  --summary                   列出新建、删除和权限变化的文件（不修改文件）
   --posix                     严格遵循 POSIX 标准
 
   -d DIR  --directory=DIR     首先切换到指定目录
   --reject-format=FORMAT      reject 文件格式（context/unified）
   --binary                    以二进制方式读写数据
+  --encoding=[FILE=]ENC       指定目标文件的编码（utf-8, utf-16le, utf-16be），
+                             默认根据 BOM 自动识别
   --read-only=BEHAVIOR        只读文件处理方式: ignore/warn/fail
 
   -v  --version               显示版本信息
   --help                      显示本帮助
 
+退出状态为 0 表示所有 hunk 都成功应用，1 表示有 hunk 应用失败，2 表示出现严重错误。
+
 Bug报告请发送至 <bug-gnu-patch@gnu.org>
 "#;
\ No newline at end of file
@@ -0,0 +1,296 @@
+use std::env;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+use crate::help;
+
+/// 消息的语言，英文原文就是消息的键（与 gettext 的 msgid 相同）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Chinese,
+}
+
+/// 当前使用的语言，按照环境变量确定，之后不再改变
+pub fn language() -> Language {
+    static LANGUAGE: OnceLock<Language> = OnceLock::new();
+    *LANGUAGE.get_or_init(|| detect_language(|name| env::var(name).ok()))
+}
+
+/// 与 gettext 相同的规则：区域设置依次取 LC_ALL、LC_MESSAGES、LANG 中第一个非空的；
+/// 区域设置为 C/POSIX 时始终用英文，否则 LANGUAGE 中列出的语言优先
+fn detect_language(var: impl Fn(&str) -> Option<String>) -> Language {
+    let var = |name: &str| var(name).filter(|v| !v.is_empty());
+    let locale = var("LC_ALL").or_else(|| var("LC_MESSAGES")).or_else(|| var("LANG"));
+    let Some(locale) = locale else {
+        return Language::English;
+    };
+    if locale == "C" || locale == "POSIX" || locale.starts_with("C.") {
+        return Language::English;
+    }
+    var("LANGUAGE")
+        .and_then(|list| list.split(':').find_map(language_of))
+        .or_else(|| language_of(&locale))
+        .unwrap_or(Language::English)
+}
+
+/// 区域名（如 zh_CN.UTF-8、en_US）对应的语言，没有翻译的语言返回 None
+fn language_of(locale: &str) -> Option<Language> {
+    let lang = locale.split(['_', '.', '@']).next().unwrap_or("");
+    match lang {
+        "zh" => Some(Language::Chinese),
+        "en" | "C" | "POSIX" => Some(Language::English),
+        _ => None,
+    }
+}
+
+/// 查找消息的翻译，没有翻译时返回原文
+pub fn gettext(msgid: &'static str) -> &'static str {
+    let catalog = match language() {
+        Language::English => return msgid,
+        Language::Chinese => ZH_CN,
+    };
+    catalog.iter().find(|(id, _)| *id == msgid).map_or(msgid, |(_, msgstr)| msgstr)
+}
+
+/// 用参数填充消息中的占位符：{} 依次取下一个参数，{N} 取第 N 个参数（从 0 开始），
+/// 这样译文可以调整参数的顺序；{{ 和 }} 表示花括号本身
+pub fn format(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(r) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+            out.push_str(&rest[..1]);
+            rest = r;
+            continue;
+        }
+        let end = match rest.find('}') {
+            Some(end) if rest.starts_with('{') => end,
+            _ => {
+                out.push_str(&rest[..1]);
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let index = match &rest[1..end] {
+            "" => {
+                next += 1;
+                next - 1
+            }
+            n => n.parse().unwrap_or(usize::MAX),
+        };
+        match args.get(index) {
+            Some(arg) => out.push_str(&arg.to_string()),
+            None => out.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 翻译消息并填充参数：tr!("patching file {}", name)
+macro_rules! tr {
+    ($msgid:expr) => {
+        $crate::i18n::gettext($msgid)
+    };
+    ($msgid:expr, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::format($crate::i18n::gettext($msgid), &[$(&$arg),+])
+    };
+}
+pub(crate) use tr;
+
+/// 简体中文消息目录：(英文原文, 译文)
+static ZH_CN: &[(&str, &str)] = &[
+    (help::HELP_TEXT, help::HELP_TEXT_ZH),
+    // version.rs
+    ("GNU patch (Rust rewrite) v0.1.0", "GNU patch (Rust重写版) v0.1.0"),
+    ("Copyright (C) 2025 Free Software Foundation, Inc.", "版权所有 (c) 2025 Free Software Foundation, Inc."),
+    // main.rs
+    ("extra operand", "参数错误，多余的操作数"),
+    ("failed to parse patch: {}", "解析补丁失败: {}"),
+    ("Only garbage was found in the patch input.", "补丁中没有找到任何改动"),
+    ("can't find file to patch ({}), skipping patch", "找不到要修补的文件（{}），跳过此补丁"),
+    ("refusing to patch unsafe path: {}", "不允许修补不安全路径：{}"),
+    ("checking file {}", "正在检查文件 {}"),
+    ("patching file {}", "正在修补文件 {}"),
+    ("can't write {}: {}", "无法写入 {}: {}"),
+    ("patch did not apply completely, no files were changed", "补丁没有完整应用，没有修改任何文件"),
+    (
+        "can't write {}: {}; all changes made in this run were rolled back",
+        "无法写入 {}: {}，已撤销本次运行中的所有修改",
+    ),
+    ("can't read input file: {}", "读取输入文件失败: {}"),
+    ("{} (renamed from {})", "{}（由 {} 改名）"),
+    ("{} (copied from {})", "{}（从 {} 复制）"),
+    (
+        "The next patch would create the file {}, which already exists!  Skipping patch.",
+        "补丁要新建文件 {}，但该文件已经存在，跳过此补丁",
+    ),
+    ("Not deleting file {} as content differs from patch", "文件 {} 的内容与补丁不符，不删除"),
+    ("File {} has mode {}, but the patch expects mode {}", "文件 {} 的权限为 {}，但补丁中为 {}"),
+    ("corrupt binary patch at line {}", "二进制补丁第 {} 行已损坏"),
+    ("checking symbolic link {}", "检查符号链接 {}"),
+    ("patching symbolic link {}", "修补符号链接 {}"),
+    (
+        "refusing to create symbolic link {} pointing to unsafe path: {}",
+        "不允许创建指向不安全路径的符号链接 {}: {}",
+    ),
+    ("binary patch does not apply to {}", "二进制补丁无法应用到 {}"),
+    (
+        "the patch applies to {} ({}), which does not match the current contents",
+        "补丁针对的 {} ({}) 与文件当前的内容不符",
+    ),
+    (
+        "patch to {} creates incorrect result (expecting {}, got {})",
+        "补丁应用到 {} 的结果与补丁中的新文件不符（应为 {}，实际为 {}）",
+    ),
+    (
+        "{} matches the patched file in the patch ({})",
+        "{} 与补丁中的新文件一致 ({})",
+    ),
+    ("ed script failed: {}", "执行 ed 脚本失败: {}"),
+    ("can't write {}.rej: {}", "无法写入 {}.rej: {}"),
+    ("can't create output file: {}", "创建输出文件失败: {}"),
+    ("Hunk #{} succeeded at {}{}.", "Hunk #{} 合并成功，位于第 {} 行{}"),
+    (" with fuzz {}", "，模糊匹配 {} 行"),
+    (" ignoring white space", "，忽略空白"),
+    (" (offset {} line)", "（偏移 {} 行）"),
+    (" (offset {} lines)", "（偏移 {} 行）"),
+    ("Hunk #{} NOT MERGED at {}, conflict markers inserted.", "Hunk #{} 存在冲突，已在第 {} 行写入冲突标记，需人工处理"),
+    ("Hunk #{} already applied, skipped.", "Hunk #{} 已经应用过，跳过"),
+    ("Hunk #{} FAILED at {} (--strict: would need {}).", "Hunk #{0} 被 --strict 拒绝，需要{2}才能应用在第 {1} 行"),
+    ("Hunk #{} FAILED at {}.", "Hunk #{} 失败，第 {} 行附近找不到匹配的上下文"),
+    ("offset {} line", "偏移 {} 行"),
+    ("offset {} lines", "偏移 {} 行"),
+    ("fuzz {}", "模糊匹配 {} 行"),
+    ("white space relaxation", "忽略空白"),
+    (", ", "，"),
+    ("{} out of {} hunk FAILED -- saving rejects to file {}", "{1} 个 hunk 中有 {0} 个失败，已保存到 {2}"),
+    ("{} out of {} hunks FAILED -- saving rejects to file {}", "{1} 个 hunk 中有 {0} 个失败，已保存到 {2}"),
+    ("option '--{}' requires an argument", "选项 '--{}' 需要一个参数"),
+    ("unrecognized option '--{}'", "无法识别的选项 '--{}'"),
+    ("option requires an argument -- '{}'", "选项 '-{}' 需要一个参数"),
+    ("invalid option -- '{}'", "无效选项 -- '{}'"),
+    ("unrecognized encoding: {}", "无法识别的编码: {}"),
+    ("argument to -D is not an identifier: {}", "-D 的参数不是合法的宏名: {}"),
+    ("strip count {} is not a number", "-p 的参数不是合法的数字: {}"),
+    ("fuzz factor {} is not a number", "-F 的参数不是合法的数字: {}"),
+    (
+        "invalid version control type: {} (valid types: none, simple, numbered, existing)",
+        "无效的备份方式: {}（可选 none, simple, numbered, existing）",
+    ),
+    ("option argument must not be empty", "选项的参数不能为空"),
+    ("invalid output format: {} (valid formats: text, json)", "无效的输出格式: {}（可选 text, json）"),
+    ("Hmm...  Looks like a unified diff to me...", "嗯……看起来像是 unified diff……"),
+    ("Hmm...  Looks like a new-style context diff to me...", "嗯……看起来像是新式的 context diff……"),
+    ("Hmm...  Looks like a context diff to me...", "嗯……看起来像是 context diff……"),
+    ("Hmm...  Looks like a normal diff to me...", "嗯……看起来像是 normal diff……"),
+    ("Hmm...  Looks like an ed script to me...", "嗯……看起来像是 ed 脚本……"),
+    ("The text leading up to this was:", "在此之前的文本是："),
+    ("done", "完成"),
+    // merge.rs
+    ("Looking for hunk #{} near line {}...", "在第 {1} 行附近查找 hunk #{0}……"),
+    ("  no match, trying fuzz {}{}", "  没有找到匹配，尝试模糊匹配 {} 行{}"),
+    // pch.rs
+    ("can't open patch file: {}", "打开patch文件失败: {}"),
+    ("can't read patch file: {}", "读取patch文件失败: {}"),
+    ("line {}: malformed unified diff hunk header: {}", "第 {} 行：unified diff hunk 头格式错误: {}"),
+    (
+        "line {}: unexpected end of hunk, {} old and {} new lines missing",
+        "第 {} 行：hunk 意外结束，还缺少 {} 行旧文本和 {} 行新文本",
+    ),
+    (
+        "line {}: hunk does not match the line counts in its @@ header: {}",
+        "第 {} 行：hunk 内容与 @@ 头中的行数不符: {}",
+    ),
+    ("malformed context diff range: {}", "context diff 范围格式错误: {}"),
+    ("unexpected end of context diff hunk", "context diff hunk 意外结束"),
+    ("context diff hunk is missing the old range: {}", "context diff hunk 缺少旧文本范围: {}"),
+    ("context diff hunk is missing the new range: {}", "context diff hunk 缺少新文本范围: {}"),
+    ("context diff hunk has two empty sections: {}", "context diff hunk 两节都为空: {}"),
+    ("context diff hunk old lines do not match range {}", "context diff hunk 旧文本行数与范围 {} 不符"),
+    ("context diff hunk new lines do not match range {}", "context diff hunk 新文本行数与范围 {} 不符"),
+    ("context diff hunk sections have different context: {}", "context diff hunk 两节的上下文行不一致: {}"),
+    ("ed scripts can't be applied in reverse ({})", "ed 脚本不能反向应用（{}）"),
+    ("copies can't be applied in reverse ({})", "复制不能反向应用（{}）"),
+    ("binary patch has no reverse section ({})", "二进制补丁中没有反向应用所需的一段（{}）"),
+    (
+        "context diff hunk sections have different numbers of context lines: {}",
+        "context diff hunk 两节的上下文行数不一致: {}",
+    ),
+    // ed.rs
+    ("ed script line {}: refusing to run unsafe command: {}", "ed 脚本第 {} 行：拒绝执行不安全的命令: {}"),
+    ("ed script line {}: unsupported command: {}", "ed 脚本第 {} 行：不支持的命令: {}"),
+    ("ed script line {}: unsupported address: {}", "ed 脚本第 {} 行：不支持的地址: {}"),
+    (
+        "unexpected end of ed script: command at line {} has no terminating \".\" line",
+        "ed 脚本意外结束：第 {} 行的命令缺少 \".\" 结束行",
+    ),
+    ("ed command #{}: address {},{} out of range", "ed 命令 #{}: 地址 {},{} 超出范围"),
+    ("ed command #{}: s/.// did not match", "ed 命令 #{}: s/.// 匹配失败"),
+    // encoding.rs
+    ("UTF-16 file has an odd number of bytes", "UTF-16 文件的字节数不是偶数"),
+    ("UTF-16 decoding failed: {}", "UTF-16 解码失败: {}"),
+    // report.rs
+    ("{} file changed", "{} 个文件被修改"),
+    ("{} files changed", "{} 个文件被修改"),
+    ("{} insertion(+)", "{} 行插入(+)"),
+    ("{} insertions(+)", "{} 行插入(+)"),
+    ("{} deletion(-)", "{} 行删除(-)"),
+    ("{} deletions(-)", "{} 行删除(-)"),
+    (
+        "{}: {}/{} hunks apply, max offset {}, max fuzz {}",
+        "{}: {}/{} 个 hunk 可以应用，最大偏移 {} 行，最大模糊匹配 {} 行",
+    ),
+    // restore.rs
+    ("can't restore: no file name in patch ({})", "无法恢复：补丁中没有文件名（{}）"),
+    ("can't restore {}: {}", "无法恢复 {}: {}"),
+    ("would remove created file {}", "将删除新建的文件 {}"),
+    ("removed created file {}", "已删除新建的文件 {}"),
+    ("no backup file found", "找不到备份文件"),
+    ("would restore {1} from {0}", "将从 {0} 恢复 {1}"),
+    ("restored {1} from {0}", "已从 {0} 恢复 {1}"),
+    // safe.rs
+    ("refusing to access unsafe path: {}", "不允许访问不安全路径: {}"),
+    ("refusing to remove unsafe path: {}", "不允许删除不安全路径: {}"),
+    ("refusing to create unsafe path: {}", "不允许创建不安全路径: {}"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(n, _)| *n == name).map(|(_, v)| v.to_string())
+        };
+        assert_eq!(detect_language(env(&[])), Language::English);
+        assert_eq!(detect_language(env(&[("LANG", "zh_CN.UTF-8")])), Language::Chinese);
+        assert_eq!(detect_language(env(&[("LANG", "zh_CN.UTF-8"), ("LC_ALL", "C")])), Language::English);
+        assert_eq!(detect_language(env(&[("LANG", "de_DE"), ("LANGUAGE", "fr:zh_TW")])), Language::Chinese);
+        assert_eq!(detect_language(env(&[("LANGUAGE", "zh_CN")])), Language::English);
+        assert_eq!(detect_language(env(&[("LC_MESSAGES", "en_US"), ("LANG", "zh_CN")])), Language::English);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("{} of {}", &[&1, &"two"]), "1 of two");
+        assert_eq!(format("{1} of {0}", &[&1, &"two"]), "two of 1");
+        assert_eq!(format("{{}} {}", &[&3]), "{} 3");
+        assert_eq!(format("{} {}", &[&3]), "3 {}");
+    }
+
+    #[test]
+    fn test_catalog_placeholders() {
+        // 译文的占位符个数必须与原文一致
+        let count = |s: &str| s.matches('{').count() - 2 * s.matches("{{").count();
+        for (msgid, msgstr) in ZH_CN {
+            assert_eq!(count(msgid), count(msgstr), "{}", msgid);
+        }
+    }
+}
\ No newline at end of file
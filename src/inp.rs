@@ -1,30 +1,64 @@
-use crate::common::Idx;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use crate::common::{self, Idx, LineEnding};
+use crate::encoding::{self, Encoding};
+use std::io;
+use std::sync::atomic::Ordering;
 
 pub struct ILine<'a> {
-    pub ptr: &'a str,
-    pub size: usize,
+    pub ptr: &'a [u8],
 }
 
 pub struct InputFile {
-    pub lines: Vec<String>,
+    /// 每行的原始字节（不含 '\n'，CRLF 行保留 '\r'），任意字节都原样保留
+    pub lines: Vec<Vec<u8>>,
+    /// 最后一行没有换行符
+    pub missing_newline: bool,
+    /// 文件中占多数的行尾约定
+    pub line_ending: LineEnding,
+    /// 文件原来的编码，输出时按它重新编码
+    pub encoding: Encoding,
 }
 
 impl InputFile {
-    pub fn from_file(filename: &str) -> io::Result<Self> {
-        let file = File::open(filename)?;
-        let reader = BufReader::new(file);
+    /// 把 data 当作文件 filename 的内容（如同一次运行中暂存的上一次修补结果）
+    pub fn from_bytes(data: &[u8], filename: &str) -> io::Result<Self> {
 
-        let lines = reader.lines()
-            .map(|l| l.unwrap_or_default())
-            .collect::<Vec<_>>();
+        // 除非指定了 --binary，先把 UTF-16 或带 BOM 的文件转成 UTF-8 再处理
+        let encoding = if common::BINARY.load(Ordering::Relaxed) {
+            Encoding::UTF8
+        } else {
+            encoding::detect(data, encoding::forced_charset(filename))
+        };
+        let data = encoding::decode(data, encoding)?;
 
-        Ok(InputFile { lines })
+        let mut lines = Vec::new();
+        let mut missing_newline = false;
+        let mut crlf_lines = 0;
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            let mut line = line.to_vec();
+            if line.last() == Some(&b'\n') {
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    crlf_lines += 1;
+                }
+            } else {
+                missing_newline = true;
+            }
+            lines.push(line);
+        }
+
+        let lf_lines = lines.len() - usize::from(missing_newline) - crlf_lines;
+        let line_ending = if crlf_lines > lf_lines { LineEnding::CrLf } else { LineEnding::Lf };
+
+        Ok(InputFile { lines, missing_newline, line_ending, encoding })
     }
 
     pub fn ifetch(&self, line: Idx) -> Option<ILine<'_>> {
-        self.lines.get(line - 1).map(|s| ILine { ptr: s, size: s.len() })
+        self.lines.get(line - 1).map(|s| ILine { ptr: s })
+    }
+
+    /// 第 line 行（1-based）后面是否有换行符
+    pub fn has_newline(&self, line: Idx) -> bool {
+        line != self.lines.len() || !self.missing_newline
     }
 
     pub fn num_lines(&self) -> Idx {
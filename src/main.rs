@@ -1,87 +1,1042 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::process;
+use std::sync::atomic::Ordering;
 
+use common::ExitStatus;
+use i18n::tr;
+
+mod backupfile;
+mod binary;
 mod common;
+mod ed;
+mod encoding;
+mod i18n;
 mod inp;
 mod pch;
+mod reject;
+mod report;
+mod restore;
 mod util;
-mod bestmatch;
 mod merge;
 mod safe;
+mod sha1;
 mod help;
+mod version;
 
 fn main() {
-    // 显示版本信息
-    println!("GNU patch (Rust重写版) v0.1.0");
-    println!("版权所有 (c) 2025 Free Software Foundation, Inc.");
-    println!("用法: patch [选项] 原文件 补丁文件\n");
+    // 与 GNU patch 一样，POSIXLY_CORRECT 下默认不做 --backup-if-mismatch
+    if env::var_os("POSIXLY_CORRECT").is_some() {
+        common::BACKUP_IF_MISMATCH.store(false, Ordering::Relaxed);
+    }
+    // PATCH_DEBUG 打开调试跟踪，输出每个候选位置及其代价
+    if env::var("PATCH_DEBUG").is_ok_and(|v| !v.is_empty() && v != "0") {
+        common::DEBUG.store(true, Ordering::Relaxed);
+    }
 
     let args: Vec<String> = env::args().collect();
+    let mut operands = get_some_switches(&args).into_iter();
+
+    let orig_file = operands.next();
+    if let Some(dir) = orig_file.as_deref().and_then(|file| Path::new(file).parent()) {
+        let _ = safe::TRUSTED_DIR.set(dir.to_path_buf());
+    }
+    // -B 的前缀以 "/" 结尾时本身就是目录，否则取它所在的目录
+    if let Some(prefix) = common::BACKUP_PREFIX.get() {
+        let dir = if prefix.ends_with('/') { Some(Path::new(prefix)) } else { Path::new(prefix).parent() };
+        let _ = safe::BACKUP_DIR.set(dir.unwrap_or(Path::new("")).to_path_buf());
+    }
+    let patch_file = common::PATCH_FILE.get().cloned().or_else(|| operands.next());
+    if operands.next().is_some() {
+        eprintln!("{}", tr!("extra operand"));
+        ExitStatus::Trouble.exit();
+    }
+
+    // 1. 解析补丁文件
+    let mut patch_set = match pch::PatchSet::from_file(patch_file.as_deref()) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", tr!("failed to parse patch: {}", e));
+            ExitStatus::Trouble.exit();
+        }
+    };
+    if patch_set.patches.is_empty() {
+        eprintln!("{}", tr!("Only garbage was found in the patch input."));
+        ExitStatus::Trouble.exit();
+    }
+
+    if common::REVERSE.load(Ordering::Relaxed)
+        && let Err(e) = patch_set.reverse()
+    {
+        eprintln!("{}", e);
+        ExitStatus::Trouble.exit();
+    }
 
-    if args.contains(&"--help".to_string()) {
-        println!("{}", help::HELP_TEXT);
+    if common::RESTORE.load(Ordering::Relaxed) {
+        let failures = restore::restore_patch_set(&patch_set, orig_file.as_deref());
+        if failures > 0 {
+            ExitStatus::Trouble.exit();
+        }
         return;
     }
 
-    //let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        eprintln!("参数错误，需指定原文件和补丁文件");
-        process::exit(1);
+    // 2. 逐个文件应用补丁，出错时跳过该文件继续处理后面的文件
+    //
+    // --atomic 模式下先把所有结果写入临时文件，全部成功后才统一替换目标文件；
+    // --dry-run 模式下结果只保存在内存中，不写入任何文件。
+    //
+    // --check/--stat/--numstat/--summary 只试应用补丁并输出统计，相当于 --dry-run。
+    let report = [&common::CHECK, &common::STAT, &common::NUMSTAT, &common::SUMMARY]
+        .iter()
+        .any(|flag| flag.load(Ordering::Relaxed));
+    if report {
+        common::DRY_RUN.store(true, Ordering::Relaxed);
     }
+    let atomic = common::ATOMIC.load(Ordering::Relaxed);
+    let dry_run = common::DRY_RUN.load(Ordering::Relaxed);
+    let json = common::JSON.load(Ordering::Relaxed);
+    let quiet = report || json;
+    let mut status = ExitStatus::Success;
+    let mut seen = HashSet::new();
+    let mut staged: Vec<Staged> = Vec::new();
+    let mut stats = Vec::new();
+    let mut rejects = reject::RejectFiles::default();
+    let patch_name = match patch_file.as_deref() {
+        None | Some("-") => "<stdin>",
+        Some(name) => name,
+    };
 
-    let orig_file = &args[1];
-    let patch_file = &args[2];
+    // 重命名和复制以修补前的源文件为准，先把它们全部读出来，
+    // 这样两个文件互换时不会读到本次运行中已经改写过的内容。
+    // 改名后源文件被删除，除非它同时是其他补丁的目标文件（如互换）。
+    let preimages = read_preimages(&patch_set);
+    let targets: HashSet<String> = patch_set.patches.iter().filter_map(pch::Patch::target_file).collect();
+    let renamed: HashSet<String> = patch_set.patches.iter()
+        .filter(|p| p.header.operation == Some(pch::GitOperation::Rename))
+        .filter_map(pch::Patch::source_file)
+        .collect();
 
-    // 1. 读取输入文件
-    let input = match inp::InputFile::from_file(orig_file) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("读取输入文件失败: {}", e);
-            process::exit(2);
+    for patch in &mut patch_set.patches {
+        let target = orig_file.clone().or_else(|| patch.target_file());
+        let source_name = patch.source_file();
+        // 补丁中的文件名不能指向工作目录以外
+        if let Some(name) = target.iter().chain(&source_name).find(|name| !safe::is_allowed(Path::new(name))) {
+            let msg = tr!("refusing to patch unsafe path: {}", name);
+            eprintln!("{}", msg);
+            stats.push(report::FileStat::error(name, patch, msg));
+            status = ExitStatus::Trouble;
+            continue;
+        }
+        let preimage = source_name.as_ref().map(|name| preimages.get(name));
+        let (Some(target), None | Some(Some(_))) = (target, preimage) else {
+            let msg = tr!("can't find file to patch ({}), skipping patch", patch.file_names());
+            eprintln!("{}", msg);
+            let name = patch.candidate_files().into_iter().next().unwrap_or_default();
+            stats.push(report::FileStat::error(&name, patch, msg));
+            status = ExitStatus::Trouble;
+            continue;
+        };
+        let source = source_name.as_deref().zip(preimage.flatten()).map(|(name, preimage)| Source {
+            name,
+            preimage,
+            remove: patch.header.operation == Some(pch::GitOperation::Rename) && !targets.contains(name),
+            replace: renamed.contains(&target),
+        });
+        if !quiet {
+            print_narration(patch);
+            let name = match (patch.header.operation, &source) {
+                (Some(pch::GitOperation::Rename), Some(source)) => tr!("{} (renamed from {})", target, source.name),
+                (Some(pch::GitOperation::Copy), Some(source)) => tr!("{} (copied from {})", target, source.name),
+                _ => target.clone(),
+            };
+            match (dry_run, patch.is_symlink()) {
+                (true, false) => util::say(&tr!("checking file {}", name)),
+                (false, false) => util::say(&tr!("patching file {}", name)),
+                (true, true) => util::say(&tr!("checking symbolic link {}", name)),
+                (false, true) => util::say(&tr!("patching symbolic link {}", name)),
+            }
         }
+
+        // 同一个文件再次出现时，在上一次暂存的结果上继续修补
+        let previous = staged.iter().position(|s| s.target == target);
+        let stage = match stage_file(&target, previous.map(|i| &staged[i]), source, patch, &mut rejects) {
+            Ok(stage) => stage,
+            Err(msg) => {
+                eprintln!("{}", msg);
+                stats.push(report::FileStat::error(&target, patch, msg));
+                status = ExitStatus::Trouble;
+                continue;
+            }
+        };
+        for warning in &stage.warnings {
+            eprintln!("{}", warning);
+        }
+        if !quiet {
+            print_hunk_results(&stage);
+        }
+        print_diagnostics(&target, patch, &stage.results, patch_name);
+        status = status.worst(stage.status);
+        stats.push(report::FileStat {
+            reject_file: stage.reject_file.clone(),
+            deleted: stage.delete,
+            exact: stage.exact,
+            ..report::FileStat::new(&target, patch, &stage.results)
+        });
+
+        if !atomic && !dry_run {
+            let first_time = seen.insert(target.clone());
+            match commit_file(&stage, first_time, &mut Vec::new()) {
+                Ok(backup) => stats.last_mut().unwrap().backup = backup,
+                Err(e) => {
+                    stage.discard();
+                    eprintln!("{}", tr!("can't write {}: {}", target, e));
+                    status = ExitStatus::Trouble;
+                }
+            }
+        } else if let Some(i) = previous {
+            let stage = Staged {
+                mismatch: staged[i].mismatch || stage.mismatch,
+                created: staged[i].created,
+                remove_source: staged[i].remove_source.take().or(stage.remove_source),
+                ..stage
+            };
+            std::mem::replace(&mut staged[i], stage).discard();
+        } else {
+            staged.push(stage);
+        }
+    }
+
+    if atomic {
+        if status != ExitStatus::Success {
+            staged.iter().for_each(Staged::discard);
+            eprintln!("{}", tr!("patch did not apply completely, no files were changed"));
+        } else if !dry_run {
+            match commit_all(&staged) {
+                Ok(backups) => {
+                    for (target, backup) in backups {
+                        if let Some(stat) = stats.iter_mut().find(|s| s.path == target) {
+                            stat.backup = backup;
+                        }
+                    }
+                }
+                Err((target, e)) => {
+                    eprintln!("{}", tr!("can't write {}: {}; all changes made in this run were rolled back", target, e));
+                    status = ExitStatus::Trouble;
+                }
+            }
+        }
+    }
+
+    if common::STAT.load(Ordering::Relaxed) {
+        report::print_stat(&stats);
+    }
+    if common::NUMSTAT.load(Ordering::Relaxed) {
+        report::print_numstat(&stats);
+    }
+    if common::SUMMARY.load(Ordering::Relaxed) {
+        report::print_summary(&stats);
+    }
+    if json {
+        report::print_json(&stats);
+    }
+
+    // 有 hunk 失败时退出状态为 1，严重错误为 2
+    if status != ExitStatus::Success {
+        status.exit();
+    }
+}
+
+/// 已经算出修补结果、等待替换目标文件的文件
+struct Staged {
+    target: String,
+    /// 修补后的文件内容
+    data: Vec<u8>,
+    /// 写有修补结果的临时文件，--dry-run 时为 None
+    temp_path: Option<String>,
+    /// 有 hunk 没有原样应用（用于 --backup-if-mismatch）
+    mismatch: bool,
+    /// 各 hunk 的应用结果对应的退出状态
+    status: ExitStatus,
+    /// 本次修补中每个 hunk 的应用结果
+    results: Vec<merge::HunkResult>,
+    /// 保存被拒绝的 hunk 的 .rej 文件
+    reject_file: Option<String>,
+    /// 目标文件原来不存在，由补丁新建
+    created: bool,
+    /// 修补后删除目标文件（补丁头中新文件为 /dev/null，或 -E 时结果为空）
+    delete: bool,
+    /// 写入目标文件后要删除的文件（重命名的源文件）
+    remove_source: Option<String>,
+    /// 目标文件要设置的权限：重命名或复制时取自源文件，否则取自原来的目标文件
+    permissions: Option<fs::Permissions>,
+    /// 修补结果是符号链接，data 为它指向的路径
+    symlink: bool,
+    /// 结果与补丁 index 行中新文件的 hash 一致，没有 hash 时为 None
+    exact: Option<bool>,
+    /// 无法按补丁处理整个文件，或者文件权限与补丁不符时的警告
+    warnings: Vec<String>,
+}
+
+impl Staged {
+    /// 放弃修补结果，删除临时文件以及为新建文件创建的目录
+    fn discard(&self) {
+        if let Some(temp_path) = &self.temp_path {
+            let _ = safe::safe_unlink(temp_path);
+            if self.created {
+                util::remove_empty_dirs(&self.target);
+            }
+        }
+    }
+}
+
+/// 重命名或复制的源文件修补前的内容和权限，符号链接的内容为它指向的路径
+struct Preimage {
+    data: Vec<u8>,
+    permissions: fs::Permissions,
+    symlink: bool,
+}
+
+/// 读出补丁中所有重命名和复制的源文件，读不到的文件不在结果中
+fn read_preimages(patch_set: &pch::PatchSet) -> HashMap<String, Preimage> {
+    patch_set.patches.iter()
+        .filter_map(pch::Patch::source_file)
+        .filter_map(|name| {
+            let metadata = fs::symlink_metadata(&name).ok()?;
+            let symlink = metadata.file_type().is_symlink();
+            let data = if symlink {
+                safe::safe_readlink(&name).ok()?.into_bytes()
+            } else {
+                safe::safe_read(&name).ok()?
+            };
+            Some((name, Preimage { data, permissions: metadata.permissions(), symlink }))
+        })
+        .collect()
+}
+
+/// 重命名或复制的源文件
+struct Source<'a> {
+    name: &'a str,
+    preimage: &'a Preimage,
+    /// 修补后删除源文件（重命名，且源文件不是其他补丁的目标文件）
+    remove: bool,
+    /// 可以覆盖已经存在的目标文件（目标文件本身也被改名，如两个文件互换）
+    replace: bool,
+}
+
+/// 把一个文件的补丁应用到 target 上，结果写入 target 同目录的临时文件
+///
+/// previous 为同一次运行中暂存的上一次修补结果，此时在它的基础上修补；
+/// 重命名或复制时在源文件修补前的内容上修补。找不到应用位置的 hunk 写入
+/// target.rej。新建文件时先创建所在的目录；要新建的文件已经存在且不为空，
+/// 或者重命名、复制的目标文件已经存在时，整个补丁按失败处理。
+fn stage_file(
+    target: &str,
+    previous: Option<&Staged>,
+    source: Option<Source>,
+    patch: &mut pch::Patch,
+    rejects: &mut reject::RejectFiles,
+) -> Result<Staged, String> {
+    let dry_run = common::DRY_RUN.load(Ordering::Relaxed);
+    let exists = match previous {
+        Some(stage) => !stage.delete,
+        None => fs::symlink_metadata(target).is_ok(),
     };
 
-    // 2. 解析补丁文件
-    let patch = match pch::Patch::from_file(patch_file) {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("解析补丁失败: {}", e);
-            process::exit(3);
+    // 读取输入文件，新建文件时从空文件开始；符号链接的内容为它指向的路径。
+    // 已经存在的符号链接按链接读取，要新建的文件已经存在时才能按冲突报告
+    let link_input = match (previous, &source) {
+        (Some(stage), _) => stage.symlink,
+        (None, Some(source)) => source.preimage.symlink,
+        (None, None) if exists => util::git_file_mode(target) == Some(util::SYMLINK_MODE),
+        (None, None) => patch.header.old_mode == Some(util::SYMLINK_MODE),
+    };
+    let data = match (previous, &source) {
+        (Some(stage), _) => Cow::Borrowed(&stage.data[..]),
+        (None, Some(source)) => Cow::Borrowed(&source.preimage.data[..]),
+        (None, None) if !exists && patch.creates_file() => Cow::Borrowed(&b""[..]),
+        (None, None) => {
+            let data = if link_input {
+                safe::safe_readlink(target).map(String::into_bytes)
+            } else {
+                safe::safe_read(target)
+            };
+            Cow::Owned(data.map_err(|e| tr!("can't read input file: {}", e))?)
         }
     };
+    let mut permissions = match (previous, &source) {
+        (Some(stage), _) => stage.permissions.clone(),
+        (None, Some(source)) => Some(source.preimage.permissions.clone()),
+        (None, None) => fs::metadata(target).ok().map(|m| m.permissions()),
+    };
+    let actual_mode = match (previous, &source) {
+        (Some(stage), _) if stage.symlink => Some(util::SYMLINK_MODE),
+        (Some(stage), _) => stage.permissions.as_ref().map(util::git_mode),
+        (None, Some(source)) if source.preimage.symlink => Some(util::SYMLINK_MODE),
+        (None, Some(source)) => Some(util::git_mode(&source.preimage.permissions)),
+        (None, None) => util::git_file_mode(target),
+    };
 
-    // 3. 打开输出文件
-    let output_path = format!("{}.patched", orig_file);
-    let output_file = match std::fs::File::create(&output_path) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("创建输出文件失败: {}", e);
-            process::exit(4);
+    // git 补丁中的旧权限与文件不符时只给出警告，新权限在替换文件时设置；
+    // 符号链接没有自己的权限
+    let mut warnings = Vec::new();
+    if let (Some(expected), Some(actual)) = (patch.header.old_mode, actual_mode)
+        && (util::is_regular_mode(expected) || expected == util::SYMLINK_MODE)
+        && actual != expected
+    {
+        let (actual, expected) = (format!("{:06o}", actual), format!("{:06o}", expected));
+        warnings.push(tr!("File {} has mode {}, but the patch expects mode {}", target, actual, expected));
+    }
+    let symlink = patch.header.new_mode.map_or(link_input, |mode| mode == util::SYMLINK_MODE);
+    if link_input || symlink {
+        permissions = None;
+    }
+    if let Some(mode) = patch.header.new_mode
+        && util::is_regular_mode(mode)
+    {
+        permissions = Some(util::git_permissions(permissions.as_ref(), mode));
+    }
+
+    let conflict = exists && match &source {
+        Some(source) => !source.replace,
+        None => patch.creates_file() && !data.is_empty(),
+    };
+    let mut refusal = conflict.then(|| {
+        tr!("The next patch would create the file {}, which already exists!  Skipping patch.", target)
+    });
+
+    // index 行中有 hash 时，确认输入正是补丁的旧文件，修补后确认结果正是补丁的新文件。
+    // 不符时给出警告；--strict 时以及二进制补丁不符时按失败处理，不修改文件。
+    let strict = common::STRICT.load(Ordering::Relaxed) || patch.binary.is_some();
+    let text = patch.binary.is_none();
+    if let Some(expected) = sha1::usable(patch.header.sha1_old.as_deref())
+        && !blob_matches(&data, target, expected, text)
+        && refusal.is_none()
+    {
+        let msg = tr!("the patch applies to {} ({}), which does not match the current contents", target, expected);
+        if strict {
+            refusal = Some(msg);
+        } else {
+            warnings.push(msg);
+        }
+    }
+    if let Some(msg) = refusal {
+        let mut results = merge::reject_all(patch);
+        let reject_file = save_rejects(rejects, target, patch, &mut results)?;
+        return Ok(Staged {
+            target: target.to_string(),
+            data: data.into_owned(),
+            temp_path: None,
+            mismatch: false,
+            status: ExitStatus::Failed,
+            results,
+            reject_file,
+            created: false,
+            delete: false,
+            remove_source: None,
+            permissions: None,
+            symlink: link_input,
+            exact: None,
+            warnings: vec![msg],
+        });
+    }
+
+    // 应用补丁：二进制补丁整体得出新内容，不符时不修改文件；文本补丁逐个 hunk 合并
+    let (out, mut results, mut status, mut write) = match &patch.binary {
+        Some(binary) => match binary.apply(&data, target) {
+            Ok(out) => (out, Vec::new(), ExitStatus::Success, true),
+            Err(msg) => {
+                warnings.push(msg);
+                (data.into_owned(), Vec::new(), ExitStatus::Failed, false)
+            }
+        },
+        None => {
+            let input = inp::InputFile::from_bytes(&data, target)
+                .map_err(|e| tr!("can't read input file: {}", e))?;
+
+            // ed 脚本在内存中执行，得到的改动按普通 hunk 应用
+            if patch.diff_type == common::DiffType::EdDiff {
+                patch.hunks = ed::ed_hunks(&patch.ed_commands, &input)
+                    .map_err(|e| tr!("ed script failed: {}", e))?;
+            }
+
+            let mut outstate = common::OutState {
+                out: Vec::new(),
+                after_newline: true,
+                zero_output: true,
+                line_ending: input.line_ending,
+                encoding: input.encoding,
+            };
+            let results = merge::merge_patch(&input, patch, &mut outstate);
+            let status = merge::patch_status(&results);
+            (outstate.out, results, status, true)
         }
     };
-    let mut outstate = common::OutState {
-        file: output_file,
-        after_newline: true,
-        zero_output: true,
+    let reject_file = save_rejects(rejects, target, patch, &mut results)?;
+
+    // -D 的输出同时包含新旧两个版本，不可能与补丁的新文件相同，不核对
+    let ifdef = text && common::IFDEF_NAME.get().is_some();
+    let exact = sha1::usable(patch.header.sha1_new.as_deref()).filter(|_| write && !ifdef).map(|expected| {
+        let id = sha1::git_blob_id(&out);
+        let exact = blob_matches(&out, target, expected, text);
+        if exact {
+            util::verbose(&tr!("{} matches the patched file in the patch ({})", target, expected));
+        } else {
+            warnings.push(tr!("patch to {} creates incorrect result (expecting {}, got {})", target, expected, id));
+            if strict {
+                status = ExitStatus::Failed;
+                write = false;
+            }
+        }
+        exact
+    });
+
+    // 补丁删除文件时结果必须为空；-E 时删除所有修补后为空的文件
+    let empty = out.is_empty();
+    if patch.deletes_file() && !empty && status == ExitStatus::Success {
+        warnings.push(tr!("Not deleting file {} as content differs from patch", target));
+        status = ExitStatus::Failed;
+    }
+    let delete = write && empty && (patch.deletes_file() || common::REMOVE_EMPTY_FILES.load(Ordering::Relaxed));
+    let created = !exists;
+
+    // 符号链接不能指向工作目录以外，--dry-run 时同样检查
+    let link = String::from_utf8_lossy(&out).into_owned();
+    if symlink && write && !delete {
+        safe::check_symlink(&link, target).map_err(|e| e.to_string())?;
+    }
+
+    // 写入临时文件，符号链接则在临时文件名上创建新的链接
+    let temp_path = if dry_run || delete || !write {
+        None
+    } else {
+        if created {
+            util::make_parent_dirs(target).map_err(|e| tr!("can't create output file: {}", e))?;
+        }
+        let temp_path = util::temp_name(target);
+        let written = if symlink { safe::safe_symlink(&link, &temp_path) } else { safe::safe_write(&temp_path, &out) };
+        written.map_err(|e| {
+            let _ = safe::safe_unlink(&temp_path);
+            tr!("can't create output file: {}", e)
+        })?;
+        Some(temp_path)
     };
 
-    // 4. 应用补丁
-    let results = merge::merge_patch(&input, &patch, &mut outstate);
+    Ok(Staged {
+        target: target.to_string(),
+        data: out,
+        temp_path,
+        mismatch: results.iter().any(merge::HunkResult::is_mismatch),
+        status,
+        results,
+        reject_file,
+        created,
+        delete,
+        remove_source: source.filter(|s| s.remove).map(|s| s.name.to_string()),
+        permissions,
+        symlink,
+        exact,
+        warnings,
+    })
+}
 
-    // 5. 显示结果
-    for (i, result) in results.iter().enumerate() {
-        match result {
+/// 把被拒绝的 hunk 写入 target.rej，返回 .rej 文件名
+///
+/// --dry-run 时只计算 hunk 在 .rej 文件中的位置，不写文件。--atomic 时有 hunk 被拒绝
+/// 就会放弃所有修改，所以不保存被拒绝的 hunk。
+fn save_rejects(
+    rejects: &mut reject::RejectFiles,
+    target: &str,
+    patch: &pch::Patch,
+    results: &mut [merge::HunkResult],
+) -> Result<Option<String>, String> {
+    let dry_run = common::DRY_RUN.load(Ordering::Relaxed);
+    if common::ATOMIC.load(Ordering::Relaxed) && !dry_run {
+        return Ok(None);
+    }
+    rejects.save(target, patch, results, dry_run).map_err(|e| tr!("can't write {}.rej: {}", target, e))
+}
+
+/// 显示每个 hunk 的应用结果
+///
+/// 与 GNU patch 一样，原样应用在标明位置上的 hunk 只在 --verbose 时显示。
+fn print_hunk_results(stage: &Staged) {
+    let results = &stage.results;
+    for (i, hunk) in results.iter().enumerate() {
+        let n = i + 1;
+        let msg = match hunk.result {
             merge::MergeResult::Clean => {
-                println!("Hunk #{} 合并成功", i + 1);
+                let at = hunk.applied_at.unwrap_or(hunk.nominal);
+                tr!("Hunk #{} succeeded at {}{}.", n, at, success_detail(hunk))
             }
             merge::MergeResult::Conflict => {
-                println!("Hunk #{} 存在冲突，需人工处理", i + 1);
+                tr!("Hunk #{} NOT MERGED at {}, conflict markers inserted.", n, hunk.nominal)
+            }
+            merge::MergeResult::AlreadyApplied => tr!("Hunk #{} already applied, skipped.", n),
+            merge::MergeResult::Rejected if hunk.is_relaxed() => {
+                let at = hunk.closest.unwrap_or(hunk.nominal);
+                let needs = relaxation(hunk).join(tr!(", "));
+                tr!("Hunk #{} FAILED at {} (--strict: would need {}).", n, at, needs)
+            }
+            merge::MergeResult::Rejected => tr!("Hunk #{} FAILED at {}.", n, hunk.nominal),
+        };
+        if hunk.is_mismatch() {
+            util::say(&msg);
+        } else {
+            util::verbose(&msg);
+        }
+    }
+    if let Some(reject_file) = &stage.reject_file {
+        let rejected = results.iter().filter(|h| h.is_rejected()).count();
+        let msg = if results.len() == 1 {
+            tr!("{} out of {} hunk FAILED -- saving rejects to file {}", rejected, results.len(), reject_file)
+        } else {
+            tr!("{} out of {} hunks FAILED -- saving rejects to file {}", rejected, results.len(), reject_file)
+        };
+        util::say(&msg);
+    }
+    util::verbose(tr!("done"));
+}
+
+/// --verbose：与 GNU patch 一样说明补丁的格式，并显示第一个 hunk 之前的文本
+fn print_narration(patch: &pch::Patch) {
+    if util::verbosity() != common::Verbosity::Verbose {
+        return;
+    }
+    let kind = match patch.diff_type {
+        common::DiffType::UniDiff => tr!("Hmm...  Looks like a unified diff to me..."),
+        common::DiffType::NewContextDiff => tr!("Hmm...  Looks like a new-style context diff to me..."),
+        common::DiffType::ContextDiff => tr!("Hmm...  Looks like a context diff to me..."),
+        common::DiffType::NormalDiff => tr!("Hmm...  Looks like a normal diff to me..."),
+        common::DiffType::EdDiff => tr!("Hmm...  Looks like an ed script to me..."),
+        common::DiffType::NoDiff | common::DiffType::GitBinaryDiff => return,
+    };
+    println!("{}", kind);
+    if !patch.leading_text.is_empty() {
+        println!("{}", tr!("The text leading up to this was:"));
+        println!("--------------------------");
+        for line in &patch.leading_text {
+            println!("|{}", String::from_utf8_lossy(util::strip_cr(line)));
+        }
+        println!("--------------------------");
+    }
+}
+
+/// 与 GNU patch 相同的成功信息后缀，如 " with fuzz 1 (offset 3 lines)"
+fn success_detail(hunk: &merge::HunkResult) -> String {
+    let mut detail = String::new();
+    if hunk.fuzz != 0 {
+        detail += &tr!(" with fuzz {}", hunk.fuzz);
+    }
+    if hunk.whitespace {
+        detail += tr!(" ignoring white space");
+    }
+    if hunk.offset.unsigned_abs() == 1 {
+        detail += &tr!(" (offset {} line)", hunk.offset);
+    } else if hunk.offset != 0 {
+        detail += &tr!(" (offset {} lines)", hunk.offset);
+    }
+    detail
+}
+
+/// 应用被 --strict 拒绝的 hunk 所需的放宽条件：偏移、模糊匹配、忽略空白
+fn relaxation(hunk: &merge::HunkResult) -> Vec<String> {
+    let mut parts = Vec::new();
+    if hunk.offset.unsigned_abs() == 1 {
+        parts.push(tr!("offset {} line", hunk.offset));
+    } else if hunk.offset != 0 {
+        parts.push(tr!("offset {} lines", hunk.offset));
+    }
+    if hunk.fuzz != 0 {
+        parts.push(tr!("fuzz {}", hunk.fuzz));
+    }
+    if hunk.whitespace {
+        parts.push(tr!("white space relaxation").to_string());
+    }
+    parts
+}
+
+/// 以编译器诊断的格式在标准错误输出失败的 hunk，便于编辑器的 quickfix 跳转
+///
+/// 第一行指向目标文件中与 hunk 最接近的位置，第二行指向补丁文件中的 hunk。
+fn print_diagnostics(target: &str, patch: &pch::Patch, results: &[merge::HunkResult], patch_name: &str) {
+    for (i, (hunk, result)) in patch.hunks.iter().zip(results).enumerate() {
+        let line = result.closest.unwrap_or(result.nominal);
+        match result.result {
+            merge::MergeResult::Rejected if result.is_relaxed() => {
+                let mut needs = Vec::new();
+                if result.offset != 0 {
+                    needs.push(format!("offset {:+}", result.offset));
+                }
+                if result.fuzz != 0 {
+                    needs.push(format!("fuzz {}", result.fuzz));
+                }
+                if result.whitespace {
+                    needs.push("whitespace-insensitive match".to_string());
+                }
+                eprintln!(
+                    "{}:{}: error: hunk #{} FAILED (--strict: would need {})",
+                    target,
+                    line,
+                    i + 1,
+                    needs.join(", "),
+                );
+            }
+            merge::MergeResult::Rejected => {
+                eprintln!("{}:{}: error: hunk #{} FAILED (expected context not found)", target, line, i + 1);
+            }
+            merge::MergeResult::Conflict => {
+                eprintln!("{}:{}: error: hunk #{} FAILED (conflict markers inserted)", target, line, i + 1);
             }
             merge::MergeResult::AlreadyApplied => {
-                println!("Hunk #{} 已经应用过", i + 1);
+                eprintln!("{}:{}: error: hunk #{} already applied", target, line, i + 1);
+            }
+            merge::MergeResult::Clean => continue,
+        }
+        eprintln!("{}:{}: note: hunk #{} is here", patch_name, hunk.patch_line, i + 1);
+    }
+}
+
+/// 文件内容是否与 index 行中的 hash 相符
+///
+/// 文本文件是 UTF-16 或带 BOM 时，补丁是对转换后的 UTF-8 文本生成的，
+/// 原样的内容和转成 UTF-8 之后的内容与 hash 相符都可以。
+fn blob_matches(data: &[u8], target: &str, expected: &str, text: bool) -> bool {
+    if sha1::matches(&sha1::git_blob_id(data), expected) {
+        return true;
+    }
+    if !text || common::BINARY.load(Ordering::Relaxed) {
+        return false;
+    }
+    let enc = encoding::detect(data, encoding::forced_charset(target));
+    enc != encoding::Encoding::UTF8
+        && encoding::decode(data, enc).is_ok_and(|utf8| sha1::matches(&sha1::git_blob_id(&utf8), expected))
+}
+
+/// 用暂存的临时文件替换目标文件，保留原文件的权限
+///
+/// 需要备份时，在替换之前把原文件改名为备份文件；同一个文件在一次运行中
+/// 只备份一次（first_time 为 false 时不再备份）。要删除的文件直接删除，
+/// 之后删除因此变空的目录。重命名时写入目标文件之后再删除源文件。
+///
+/// 返回目标文件的备份文件名；创建的所有备份文件（包括源文件的）都记入 created。
+fn commit_file(stage: &Staged, first_time: bool, created: &mut Vec<String>) -> std::io::Result<Option<String>> {
+    let backup = first_time && util::wants_backup(stage.mismatch);
+    if stage.delete {
+        let backup_file = remove_file(&stage.target, backup)?;
+        created.extend(backup_file.clone());
+        return Ok(backup_file);
+    }
+    let Some(temp_path) = &stage.temp_path else {
+        return Ok(None);
+    };
+    if let Some(permissions) = &stage.permissions {
+        fs::set_permissions(temp_path, permissions.clone())?;
+    }
+    let backup_file = if backup {
+        Some(util::create_backup(&stage.target)?)
+    } else {
+        None
+    };
+    created.extend(backup_file.clone());
+    safe::safe_rename(temp_path, &stage.target)?;
+    if let Some(source) = &stage.remove_source {
+        created.extend(remove_file(source, backup)?);
+    }
+    Ok(backup_file)
+}
+
+/// 删除 file（需要备份时改名为备份文件），之后删除因此变空的目录
+fn remove_file(file: &str, backup: bool) -> std::io::Result<Option<String>> {
+    let backup = if backup {
+        Some(util::create_backup(file)?)
+    } else {
+        safe::safe_unlink(file)?;
+        None
+    };
+    util::remove_empty_dirs(file);
+    Ok(backup)
+}
+
+/// 每个文件的备份文件名：(目标文件, 备份文件)
+type Backups = Vec<(String, Option<String>)>;
+
+/// --atomic：依次替换所有目标文件，中途出错时把已经替换的文件全部恢复原状
+///
+/// 替换前先给原文件（以及重命名时要删除的源文件）建一个硬链接（不支持时复制）
+/// 作为回滚用的副本，全部成功后再删除这些副本；回滚时同时删除已经创建的备份文件。
+/// 成功时返回每个文件的备份文件名，出错时返回出错的文件名和错误。
+fn commit_all(staged: &[Staged]) -> Result<Backups, (String, std::io::Error)> {
+    // 新建的文件没有原文件，回滚时直接删除
+    let mut originals: Vec<(&str, Option<String>)> = Vec::new();
+    let mut preserve = |file, exists: bool| {
+        let original = exists.then(|| util::temp_name(file));
+        if let Some(original) = &original {
+            util::link_or_copy(file, original)?;
+        }
+        originals.push((file, original));
+        Ok(())
+    };
+    let mut backups = Vec::new();
+    let mut created = Vec::new();
+    let mut failure = None;
+    for stage in staged {
+        let result = preserve(&stage.target, !stage.created)
+            .and_then(|_| stage.remove_source.as_deref().map_or(Ok(()), |source| preserve(source, true)))
+            .and_then(|_| commit_file(stage, true, &mut created));
+        match result {
+            Ok(backup) => backups.push((stage.target.clone(), backup)),
+            Err(e) => {
+                failure = Some((stage.target.clone(), e));
+                break;
+            }
+        }
+    }
+
+    if failure.is_some() {
+        for (target, original) in originals.iter().rev() {
+            match original {
+                // 目标文件还没被替换时两者是同一个文件，rename 什么也不做，需要另外删除
+                Some(original) => {
+                    let _ = safe::safe_rename(original, target);
+                    let _ = safe::safe_unlink(original);
+                }
+                None => {
+                    let _ = safe::safe_unlink(target);
+                }
+            }
+        }
+        for backup in &created {
+            let _ = safe::safe_unlink(backup);
+        }
+        staged.iter().for_each(Staged::discard);
+    } else {
+        for original in originals.iter().filter_map(|(_, original)| original.as_ref()) {
+            let _ = safe::safe_unlink(original);
+        }
+    }
+    failure.map_or(Ok(backups), Err)
+}
+
+/// 解析命令行选项，设置 common 中的全局开关，返回剩余的位置参数
+fn get_some_switches(args: &[String]) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut i = 1;
+
+    while i < args.len() {
+        let arg = &args[i];
+        i += 1;
+
+        if arg == "--" {
+            operands.extend(args[i..].iter().cloned());
+            break;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((n, v)) => (n, Some(v.to_string())),
+                None => (long, None),
+            };
+            let mut optarg = || match inline.clone() {
+                Some(v) => v,
+                None => {
+                    i += 1;
+                    args.get(i - 1).cloned().unwrap_or_else(|| {
+                        eprintln!("{}", tr!("option '--{}' requires an argument", name));
+                        ExitStatus::Trouble.exit();
+                    })
+                }
+            };
+            match name {
+                "help" => {
+                    println!("{}", tr!(help::HELP_TEXT));
+                    process::exit(0);
+                }
+                "version" => {
+                    version::print_version();
+                    process::exit(0);
+                }
+                "ifdef" => set_ifdef(optarg()),
+                "ed" => set_diff_type(common::DiffType::EdDiff),
+                "binary" => common::BINARY.store(true, Ordering::Relaxed),
+                "encoding" => set_encoding(optarg()),
+                "strip" => set_strip(optarg()),
+                "fuzz" => set_fuzz(optarg()),
+                "input" => set_string(&common::PATCH_FILE, optarg()),
+                "backup" => common::BACKUP.store(true, Ordering::Relaxed),
+                "backup-if-mismatch" => common::BACKUP_IF_MISMATCH.store(true, Ordering::Relaxed),
+                "no-backup-if-mismatch" => common::BACKUP_IF_MISMATCH.store(false, Ordering::Relaxed),
+                "version-control" => set_backup_type(optarg()),
+                "prefix" => set_string(&common::BACKUP_PREFIX, optarg()),
+                "basename-prefix" => set_string(&common::BACKUP_BASENAME_PREFIX, optarg()),
+                "suffix" => set_string(&common::BACKUP_SUFFIX, optarg()),
+                "restore" => common::RESTORE.store(true, Ordering::Relaxed),
+                "atomic" => common::ATOMIC.store(true, Ordering::Relaxed),
+                "dry-run" => common::DRY_RUN.store(true, Ordering::Relaxed),
+                "check" => common::CHECK.store(true, Ordering::Relaxed),
+                "stat" => common::STAT.store(true, Ordering::Relaxed),
+                "numstat" => common::NUMSTAT.store(true, Ordering::Relaxed),
+                "summary" => common::SUMMARY.store(true, Ordering::Relaxed),
+                "merge" => common::MERGE.store(true, Ordering::Relaxed),
+                "format" => set_format(optarg()),
+                "remove-empty-files" => common::REMOVE_EMPTY_FILES.store(true, Ordering::Relaxed),
+                "quiet" | "silent" => set_verbosity(common::Verbosity::Silent),
+                "verbose" => set_verbosity(common::Verbosity::Verbose),
+                "strict" => common::STRICT.store(true, Ordering::Relaxed),
+                "reverse" => common::REVERSE.store(true, Ordering::Relaxed),
+                "ignore-whitespace" => common::IGNORE_WHITESPACE.store(true, Ordering::Relaxed),
+                _ => {
+                    eprintln!("{}", tr!("unrecognized option '--{}'", name));
+                    ExitStatus::Trouble.exit();
+                }
             }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            let opts = &arg[1..];
+            for (pos, opt) in opts.char_indices() {
+                // 带参数的短选项：参数可以紧跟在选项后，也可以是下一个参数
+                let mut optarg = || {
+                    let rest = &opts[pos + opt.len_utf8()..];
+                    if !rest.is_empty() {
+                        rest.to_string()
+                    } else {
+                        i += 1;
+                        args.get(i - 1).cloned().unwrap_or_else(|| {
+                            eprintln!("{}", tr!("option requires an argument -- '{}'", opt));
+                            ExitStatus::Trouble.exit();
+                        })
+                    }
+                };
+                match opt {
+                    'e' => set_diff_type(common::DiffType::EdDiff),
+                    'v' => {
+                        version::print_version();
+                        process::exit(0);
+                    }
+                    'b' => common::BACKUP.store(true, Ordering::Relaxed),
+                    'l' => common::IGNORE_WHITESPACE.store(true, Ordering::Relaxed),
+                    'R' => common::REVERSE.store(true, Ordering::Relaxed),
+                    's' => set_verbosity(common::Verbosity::Silent),
+                    'E' => common::REMOVE_EMPTY_FILES.store(true, Ordering::Relaxed),
+                    'D' => {
+                        set_ifdef(optarg());
+                        break;
+                    }
+                    'p' => {
+                        set_strip(optarg());
+                        break;
+                    }
+                    'F' => {
+                        set_fuzz(optarg());
+                        break;
+                    }
+                    'i' => {
+                        set_string(&common::PATCH_FILE, optarg());
+                        break;
+                    }
+                    'V' => {
+                        set_backup_type(optarg());
+                        break;
+                    }
+                    'B' => {
+                        set_string(&common::BACKUP_PREFIX, optarg());
+                        break;
+                    }
+                    'Y' => {
+                        set_string(&common::BACKUP_BASENAME_PREFIX, optarg());
+                        break;
+                    }
+                    'z' => {
+                        set_string(&common::BACKUP_SUFFIX, optarg());
+                        break;
+                    }
+                    _ => {
+                        eprintln!("{}", tr!("invalid option -- '{}'", opt));
+                        ExitStatus::Trouble.exit();
+                    }
+                }
+            }
+        } else {
+            operands.push(arg.clone());
         }
     }
 
-    println!("输出已保存到: {}", output_path);
+    operands
+}
+
+fn set_verbosity(verbosity: common::Verbosity) {
+    common::VERBOSITY.store(verbosity as i32, Ordering::Relaxed);
+}
+
+fn set_diff_type(diff_type: common::DiffType) {
+    common::DIFF_TYPE.store(diff_type as i32, Ordering::Relaxed);
+}
+
+fn set_encoding(arg: String) {
+    let (file, name) = match arg.rsplit_once('=') {
+        Some((file, name)) => (Some(file.to_string()), name),
+        None => (None, arg.as_str()),
+    };
+    let Some(charset) = encoding::Encoding::from_name(name) else {
+        eprintln!("{}", tr!("unrecognized encoding: {}", name));
+        ExitStatus::Trouble.exit();
+    };
+    common::FORCED_ENCODINGS.lock().unwrap().push((file, charset));
+}
+
+fn set_ifdef(name: String) {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        eprintln!("{}", tr!("argument to -D is not an identifier: {}", name));
+        ExitStatus::Trouble.exit();
+    }
+    let _ = common::IFDEF_NAME.set(name);
+}
+
+fn set_strip(arg: String) {
+    match arg.parse::<i32>() {
+        Ok(n) if n >= 0 => common::STRIP.store(n, Ordering::Relaxed),
+        _ => {
+            eprintln!("{}", tr!("strip count {} is not a number", arg));
+            ExitStatus::Trouble.exit();
+        }
+    }
+}
+
+fn set_fuzz(arg: String) {
+    match arg.parse::<usize>() {
+        Ok(n) => common::FUZZ.store(n, Ordering::Relaxed),
+        Err(_) => {
+            eprintln!("{}", tr!("fuzz factor {} is not a number", arg));
+            ExitStatus::Trouble.exit();
+        }
+    }
+}
+
+fn set_backup_type(name: String) {
+    let Some(backup_type) = backupfile::BackupType::from_name(&name) else {
+        eprintln!("{}", tr!("invalid version control type: {} (valid types: none, simple, numbered, existing)", name));
+        ExitStatus::Trouble.exit();
+    };
+    let _ = common::BACKUP_TYPE.set(backup_type);
+}
+
+/// 设置只接受一个值的字符串选项，空字符串视为无效
+fn set_string(option: &std::sync::OnceLock<String>, value: String) {
+    if value.is_empty() {
+        eprintln!("{}", tr!("option argument must not be empty"));
+        ExitStatus::Trouble.exit();
+    }
+    let _ = option.set(value);
+}
+
+fn set_format(format: String) {
+    match format.as_str() {
+        "json" => common::JSON.store(true, Ordering::Relaxed),
+        "text" => common::JSON.store(false, Ordering::Relaxed),
+        _ => {
+            eprintln!("{}", tr!("invalid output format: {} (valid formats: text, json)", format));
+            ExitStatus::Trouble.exit();
+        }
+    }
 }
\ No newline at end of file
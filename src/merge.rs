@@ -1,73 +1,351 @@
 use crate::inp::InputFile;
 use crate::pch::{Patch, PatchHunk, LineKind};
-use crate::common::OutState;
+use crate::common::{self, ExitStatus, LineEnding, OutState};
+use crate::i18n::tr;
+use crate::util::{self, lines_match, lines_match_loose};
+use std::sync::atomic::Ordering;
 
 /// 合并结果枚举
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MergeResult {
     Clean,        // 完全干净合并
-    Conflict,     // 有冲突
-    AlreadyApplied, // 已经应用
+    Conflict,     // 有冲突（--merge 时按标明的位置写入冲突标记）
+    AlreadyApplied, // 已经应用，跳过并写入 .rej 文件
+    Rejected,     // 找不到应用位置，写入 .rej 文件
 }
 
-/// 查找 hunk 的最佳应用位置
-fn find_best_match(input: &InputFile, hunk: &PatchHunk, _fuzz: usize) -> Option<usize> {
+/// 单个 hunk 的应用情况
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HunkResult {
+    pub result: MergeResult,
+    /// 补丁中标明的位置（1-based）
+    pub nominal: usize,
+    /// 实际应用的位置（1-based），没有应用时为 None
+    pub applied_at: Option<usize>,
+    /// 实际位置相对于补丁中标明位置的偏移行数
+    pub offset: isize,
+    /// 匹配时忽略的上下文行数
+    pub fuzz: usize,
+    /// 匹配时是否忽略了空白的差异
+    pub whitespace: bool,
+    /// 写入 .rej 文件时，该 hunk 在 .rej 文件中的起始行号
+    pub reject_line: Option<usize>,
+    /// 应用失败时，原文件中与 hunk 最接近的位置（1-based）
+    pub closest: Option<usize>,
+}
+
+impl HunkResult {
+    /// 没有原样应用在标明的位置上（有偏移、模糊匹配、忽略空白或冲突）
+    pub fn is_mismatch(&self) -> bool {
+        self.result != MergeResult::Clean || self.is_relaxed()
+    }
+
+    /// 需要放宽匹配条件（偏移、模糊匹配或忽略空白）才能找到位置；
+    /// 被 --strict 拒绝的 hunk 记录的是它本来需要的放宽条件
+    pub fn is_relaxed(&self) -> bool {
+        self.offset != 0 || self.fuzz != 0 || self.whitespace
+    }
+
+    /// 应用失败（被拒绝、已经应用过而被跳过，或者有冲突）
+    pub fn is_failure(&self) -> bool {
+        self.is_rejected() || self.result == MergeResult::Conflict
+    }
+
+    /// 没有应用、要写入 .rej 文件（找不到应用位置，或者已经应用过）
+    pub fn is_rejected(&self) -> bool {
+        matches!(self.result, MergeResult::Rejected | MergeResult::AlreadyApplied)
+    }
+
+    /// 该 hunk 对应的退出状态
+    ///
+    /// --strict 不需要另外判断：需要放宽条件的 hunk 在 merge_patch 中已经被拒绝。
+    pub fn status(&self) -> ExitStatus {
+        if self.is_failure() {
+            ExitStatus::Failed
+        } else {
+            ExitStatus::Success
+        }
+    }
+}
+
+/// 一个文件所有 hunk 的退出状态
+pub fn patch_status(results: &[HunkResult]) -> ExitStatus {
+    results.iter().fold(ExitStatus::Success, |status, r| status.worst(r.status()))
+}
+
+/// 补丁中标明的应用位置；纯插入的 hunk 中 orig_start 是插入位置之前的那一行
+fn nominal_start(hunk: &PatchHunk) -> usize {
+    if hunk.orig_count == 0 { hunk.orig_start + 1 } else { hunk.orig_start }
+}
+
+/// hunk 中某一边的行：旧文本（上下文 + 删除）或新文本（上下文 + 新增）
+fn side_lines(hunk: &PatchHunk, skip: LineKind) -> Vec<&[u8]> {
+    hunk.lines.iter()
+        .filter(|l| l.kind != skip)
+        .map(|l| l.content.as_slice())
+        .collect()
+}
+
+/// hunk 的应用位置，以及找到这个位置所需的放宽条件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Placement {
+    /// 去掉被忽略的上下文之后，hunk 的应用位置（1-based）
+    at: usize,
+    /// hunk 开头被模糊匹配忽略的上下文行数
+    lead: usize,
+    /// hunk 结尾被模糊匹配忽略的上下文行数
+    trail: usize,
+    /// 是否忽略了空白的差异
+    whitespace: bool,
+}
+
+impl Placement {
+    /// 模糊匹配的行数
+    fn fuzz(&self) -> usize {
+        self.lead.max(self.trail)
+    }
+
+    /// 相对于补丁中标明位置的偏移行数
+    fn offset(&self, hunk: &PatchHunk) -> isize {
+        self.at as isize - (nominal_start(hunk) + self.lead) as isize
+    }
+
+    /// 整个 hunk（含被忽略的上下文）的起始行
+    fn start(&self) -> usize {
+        self.at.saturating_sub(self.lead).max(1)
+    }
+
+    fn is_relaxed(&self, hunk: &PatchHunk) -> bool {
+        self.offset(hunk) != 0 || self.fuzz() != 0 || self.whitespace
+    }
+}
+
+/// 查找 hunk 的最佳应用位置，找不到时返回 None
+///
+/// 依次放宽条件：先要求完全一致，whitespace 为 true 时再忽略空白的差异；
+/// 仍然找不到时忽略首尾的上下文行，最多忽略 max_fuzz 行。
+/// 应用位置不能早于 min_start。
+fn find_best_match(
+    input: &InputFile,
+    hunk: &PatchHunk,
+    max_fuzz: usize,
+    whitespace: bool,
+    min_start: usize,
+) -> Option<Placement> {
     // 提取 hunk 中的原始行（context + remove）
-    let hunk_orig_lines: Vec<String> = hunk.lines.iter()
-        .filter(|l| l.kind == LineKind::Context || l.kind == LineKind::Remove)
-        .map(|l| l.content.trim_end_matches('\n').to_string())
-        .collect();
-    
+    let hunk_orig_lines = side_lines(hunk, LineKind::Add);
+    let nominal = nominal_start(hunk);
     if hunk_orig_lines.is_empty() {
-        return Some(hunk.orig_start);
+        return Some(Placement { at: nominal.max(min_start), lead: 0, trail: 0, whitespace: false });
     }
-    
-    // 首先尝试精确匹配预期位置
-    let expected_start = hunk.orig_start;
-    let mut matches = true;
-    
-    for (i, hunk_line) in hunk_orig_lines.iter().enumerate() {
-        if let Some(input_line) = input.ifetch(expected_start + i) {
-            if input_line.ptr != hunk_line {
-                matches = false;
-                break;
-            }
-        } else {
-            matches = false;
+
+    let leading = hunk.lines.iter().take_while(|l| l.kind == LineKind::Context).count();
+    let trailing = hunk.lines.iter().rev().take_while(|l| l.kind == LineKind::Context).count();
+    let modes: &[bool] = if whitespace { &[false, true] } else { &[false] };
+    for fuzz in 0..=max_fuzz {
+        let (lead, trail) = (fuzz.min(leading), fuzz.min(trailing));
+        // 没有更多可以忽略的上下文，或者旧文本会被全部忽略
+        if lead.max(trail) < fuzz || lead + trail >= hunk_orig_lines.len() {
             break;
         }
+        let lines = &hunk_orig_lines[lead..hunk_orig_lines.len() - trail];
+        for &whitespace in modes {
+            if fuzz > 0 || whitespace {
+                let suffix = if whitespace { tr!(" ignoring white space") } else { "" };
+                util::verbose(&tr!("  no match, trying fuzz {}{}", fuzz, suffix));
+            }
+            util::debug(|| format!(
+                "hunk at line {} (patch line {}): fuzz {} (lead {}, trail {}), whitespace {}",
+                nominal, hunk.patch_line, fuzz, lead, trail, whitespace,
+            ));
+            if let Some(at) = find_lines(input, lines, nominal + lead, min_start, whitespace) {
+                return Some(Placement { at, lead, trail, whitespace });
+            }
+        }
     }
-    
-    if matches {
+    None
+}
+
+/// 去掉 hunk 首尾被模糊匹配忽略的上下文行
+fn trim_context(hunk: &PatchHunk, lead: usize, trail: usize) -> PatchHunk {
+    let mut trimmed = hunk.clone();
+    trimmed.lines.truncate(hunk.lines.len() - trail);
+    trimmed.lines.drain(..lead);
+    trimmed.orig_start += lead;
+    trimmed.new_start += lead;
+    trimmed.orig_count -= lead + trail;
+    trimmed.new_count -= lead + trail;
+    trimmed
+}
+
+/// 找出原文件中与 hunk 的旧文本最接近的位置：标明位置前后 100 行内
+/// 相同行最多的起始行，一样多时取离标明位置最近的
+fn closest_match(input: &InputFile, hunk: &PatchHunk) -> usize {
+    let old_lines = side_lines(hunk, LineKind::Add);
+    let nominal = nominal_start(hunk);
+    let last = input.num_lines().max(1);
+    let search_range = 100;
+    let score = |start: usize| {
+        old_lines.iter().enumerate()
+            .filter(|(i, l)| input.ifetch(start + i).is_some_and(|il| lines_match(il.ptr, l)))
+            .count()
+    };
+
+    let min_start = nominal.saturating_sub(search_range).max(1);
+    let max_start = (nominal + search_range).min(last);
+    (min_start..=max_start)
+        .map(|start| (score(start), start))
+        .filter(|&(n, _)| n > 0)
+        .max_by_key(|&(n, start)| (n, std::cmp::Reverse(start.abs_diff(nominal))))
+        .map_or(nominal.clamp(1, last), |(_, start)| start)
+}
+
+/// 判断 hunk 是否已经应用过：新文本已经出现在标明的位置附近
+fn is_already_applied(input: &InputFile, hunk: &PatchHunk) -> bool {
+    let new_lines = side_lines(hunk, LineKind::Remove);
+    hunk.lines.iter().any(|l| l.kind == LineKind::Add)
+        && find_lines(input, &new_lines, hunk.new_start.max(1), 1, false).is_some()
+}
+
+/// 在 expected_start 附近查找与 lines 一致的位置，whitespace 为 true 时忽略空白的差异
+///
+/// 首先尝试预期位置，再在前后 100 行内按顺序搜索，不早于 min_start。
+fn find_lines(
+    input: &InputFile,
+    lines: &[&[u8]],
+    expected_start: usize,
+    min_start: usize,
+    whitespace: bool,
+) -> Option<usize> {
+    let line_eq = if whitespace { lines_match_loose } else { lines_match };
+    let mismatch = |start: usize, i: usize, hunk_line: &[u8]| {
+        !input.ifetch(start + i).is_some_and(|input_line| line_eq(input_line.ptr, hunk_line))
+    };
+    let matches_at = |start: usize| {
+        if start < min_start.max(1) {
+            return false;
+        }
+        if !util::debugging() {
+            return !lines.iter().enumerate().any(|(i, l)| mismatch(start, i, l));
+        }
+        // 调试时统计不一致的行数作为该位置的代价
+        let cost = lines.iter().enumerate().filter(|(i, l)| mismatch(start, *i, l)).count();
+        util::debug(|| format!("  candidate line {}: cost {}/{}", start, cost, lines.len()));
+        cost == 0
+    };
+
+    if matches_at(expected_start) {
         return Some(expected_start);
     }
-    
+
     // 如果精确匹配失败，尝试在附近搜索（简单的线性搜索）
     let search_range = 100; // 在前后100行内搜索
-    let min_start = if expected_start > search_range { expected_start - search_range } else { 1 };
+    let min_start = expected_start.saturating_sub(search_range).max(min_start).max(1);
     let max_start = (expected_start + search_range).min(input.num_lines());
-    
-    for try_start in min_start..=max_start {
-        let mut found = true;
-        for (i, hunk_line) in hunk_orig_lines.iter().enumerate() {
-            if let Some(input_line) = input.ifetch(try_start + i) {
-                if input_line.ptr != hunk_line {
-                    found = false;
-                    break;
-                }
+    (min_start..=max_start).find(|&try_start| try_start != expected_start && matches_at(try_start))
+}
+
+/// 统计一段文本中条件编译指令的嵌套情况，返回(最低深度, 最终深度)
+fn cpp_depth<'a>(lines: impl Iterator<Item = &'a [u8]>) -> (isize, isize) {
+    let mut depth = 0isize;
+    let mut min = 0isize;
+    for line in lines {
+        let Some(directive) = line.trim_ascii_start().strip_prefix(b"#") else {
+            continue;
+        };
+        let directive = directive.trim_ascii_start();
+        if directive.starts_with(b"if") {
+            depth += 1;
+        } else if directive.starts_with(b"endif") {
+            depth -= 1;
+            min = min.min(depth);
+        }
+    }
+    (min, depth)
+}
+
+/// -D 模式下的一段输出行：(行类型, 要输出的文本, 行尾是否有换行符)
+type DefRow<'a> = (LineKind, &'a [u8], bool);
+
+/// 将改动分组，保证每组的旧文本与新文本中的条件编译指令各自配对
+///
+/// 如果改动里只有 #if 或只有 #endif，就把相邻的上下文行并入该组，
+/// 这样生成的 #ifndef/#else/#endif 不会和已有的条件编译交错。
+fn ifdef_regions(rows: &[DefRow<'_>]) -> Vec<(usize, usize)> {
+    let side = |rows: &[DefRow<'_>], skip: LineKind| {
+        cpp_depth(rows.iter().filter(|r| r.0 != skip).map(|r| r.1))
+    };
+
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < rows.len() {
+        if rows[i].0 == LineKind::Context {
+            i += 1;
+            continue;
+        }
+        let mut start = i;
+        while i < rows.len() && rows[i].0 != LineKind::Context {
+            i += 1;
+        }
+        let mut end = i;
+
+        loop {
+            let (old_min, old_end) = side(&rows[start..end], LineKind::Add);
+            let (new_min, new_end) = side(&rows[start..end], LineKind::Remove);
+            if (old_min < 0 || new_min < 0) && start > 0 {
+                start -= 1;
+            } else if (old_end > old_min || new_end > new_min) && end < rows.len() {
+                end += 1;
             } else {
-                found = false;
                 break;
             }
         }
-        
-        if found {
-            return Some(try_start);
+
+        match regions.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => regions.push((start, end)),
         }
+        i = i.max(end);
+    }
+    regions
+}
+
+/// 输出条件编译块内的一行：必须以换行结束，补丁中的新行按目标文件的行尾约定写入
+fn write_def_row(outstate: &mut OutState, row: &DefRow<'_>) {
+    match row.0 {
+        LineKind::Add => outstate.write_new_text(row.1, true),
+        _ => outstate.write_text(row.1, true),
+    }
+}
+
+/// 用 -D NAME 的条件编译形式输出一个 hunk
+fn write_ifdef_hunk(outstate: &mut OutState, rows: &[DefRow<'_>], name: &str) {
+    let mut pos = 0;
+    for (start, end) in ifdef_regions(rows) {
+        for row in &rows[pos..start] {
+            outstate.write_text(row.1, row.2);
+        }
+        let region = &rows[start..end];
+        let old: Vec<&DefRow<'_>> = region.iter().filter(|r| r.0 != LineKind::Add).collect();
+        let new: Vec<&DefRow<'_>> = region.iter().filter(|r| r.0 != LineKind::Remove).collect();
+
+        if old.is_empty() {
+            outstate.write_line(format!("#ifdef {}", name).as_bytes());
+            new.iter().for_each(|r| write_def_row(outstate, r));
+        } else {
+            outstate.write_line(format!("#ifndef {}", name).as_bytes());
+            old.iter().for_each(|r| write_def_row(outstate, r));
+            if !new.is_empty() {
+                outstate.write_line(b"#else");
+                new.iter().for_each(|r| write_def_row(outstate, r));
+            }
+        }
+        outstate.write_line(format!("#endif /* {} */", name).as_bytes());
+        pos = end;
+    }
+    for row in &rows[pos..] {
+        outstate.write_text(row.1, row.2);
     }
-    
-    // 如果找不到完全匹配，返回原始位置
-    Some(expected_start)
 }
 
 /// 合并单个 hunk 到输入文件
@@ -76,10 +354,14 @@ pub fn merge_hunk(
     patch_hunk: &PatchHunk,
     outstate: &mut OutState,
     start_at: usize, // 合并起始行（1-based）
+    whitespace: bool, // 比较时忽略空白的差异
 ) -> MergeResult {
+    let line_eq = if whitespace { lines_match_loose } else { lines_match };
     let mut input_idx = start_at;
     let mut hunk_idx = 0;
     let mut conflict = false;
+    let ifdef = common::IFDEF_NAME.get();
+    let mut def_rows: Vec<DefRow<'_>> = Vec::new();
 
     // 简单的三路合并主循环
     while hunk_idx < patch_hunk.lines.len() {
@@ -89,33 +371,51 @@ pub fn merge_hunk(
             LineKind::Context => {
                 // 必须和输入文件一致，否则可能冲突
                 if let Some(iline) = input.ifetch(input_idx) {
-                    if iline.ptr != hunk_line.content.trim_end_matches('\n') {
+                    if !line_eq(iline.ptr, &hunk_line.content) {
                         conflict = true;
                         // 这里可以收集冲突信息
                     }
-                    // 输出上下文行
-                    outstate.write_line(iline.ptr);
+                    // 输出上下文行，换行符状态以补丁中的新文本为准
+                    let newline = !hunk_line.missing_newline;
+                    if ifdef.is_some() {
+                        def_rows.push((LineKind::Context, iline.ptr, newline));
+                    } else {
+                        outstate.write_text(iline.ptr, newline);
+                    }
                 }
                 input_idx += 1;
             }
             LineKind::Remove => {
                 // 输入文件必须有此行，否则冲突
                 if let Some(iline) = input.ifetch(input_idx) {
-                    if iline.ptr != hunk_line.content.trim_end_matches('\n') {
+                    if !line_eq(iline.ptr, &hunk_line.content) {
                         conflict = true;
                     }
-                    // Remove 行不输出到结果文件
+                    // Remove 行不输出到结果文件，-D 模式下保留在 #ifndef 中
+                    if ifdef.is_some() {
+                        def_rows.push((LineKind::Remove, iline.ptr, true));
+                    }
                 }
                 input_idx += 1;
             }
             LineKind::Add => {
                 // 直接插入新行到输出
-                outstate.write_line(hunk_line.content.trim_end_matches('\n'));
+                let content = hunk_line.content.as_slice();
+                let newline = !hunk_line.missing_newline;
+                if ifdef.is_some() {
+                    def_rows.push((LineKind::Add, content, newline));
+                } else {
+                    outstate.write_new_text(content, newline);
+                }
             }
         }
         hunk_idx += 1;
     }
 
+    if let Some(name) = ifdef {
+        write_ifdef_hunk(outstate, &def_rows, name);
+    }
+
     if conflict {
         MergeResult::Conflict
     } else {
@@ -123,44 +423,116 @@ pub fn merge_hunk(
     }
 }
 
+/// --merge：在标明的位置用冲突标记同时写出原文件中的文本和 hunk 的新文本
+///
+/// 返回占用的原文件行数。
+fn write_conflict_hunk(input: &InputFile, hunk: &PatchHunk, outstate: &mut OutState, start_at: usize) -> usize {
+    let orig_len = hunk.lines.iter().filter(|l| l.kind != LineKind::Add).count();
+    let orig_len = orig_len.min((input.num_lines() + 1).saturating_sub(start_at));
+
+    outstate.write_line(b"<<<<<<<");
+    for k in start_at..start_at + orig_len {
+        if let Some(iline) = input.ifetch(k) {
+            outstate.write_text(iline.ptr, true);
+        }
+    }
+    outstate.write_line(b"=======");
+    for line in hunk.lines.iter().filter(|l| l.kind != LineKind::Remove) {
+        outstate.write_new_text(&line.content, true);
+    }
+    outstate.write_line(b">>>>>>>");
+    orig_len
+}
+
 /// 合并整个 patch 到输入文件
+///
+/// 找不到应用位置的 hunk 不做修改，由调用者写入 .rej 文件；
+/// 指定 --merge 时改为在标明的位置写入冲突标记。
+///
+/// 指定 --strict 时，需要偏移、模糊匹配或忽略空白才能找到位置的 hunk
+/// 按找不到位置处理，结果中记录它本来需要的放宽条件。
 pub fn merge_patch(
     input: &InputFile,
     patch: &Patch,
     outstate: &mut OutState,
-) -> Vec<MergeResult> {
+) -> Vec<HunkResult> {
     let mut results = Vec::new();
     let mut current_line = 1; // 1-based index
-    
-    for hunk in &patch.hunks {
-        // 使用最佳匹配算法定位 hunk 应用位置
-        let fuzz = 2; // 允许2行的差异
-        let where_to_apply = find_best_match(input, hunk, fuzz)
-            .unwrap_or(hunk.orig_start);
-        
+    let merge = common::MERGE.load(Ordering::Relaxed);
+    let strict = common::STRICT.load(Ordering::Relaxed);
+    // --strict 时也按放宽的条件查找，以便报告需要怎样放宽
+    let whitespace = strict || common::IGNORE_WHITESPACE.load(Ordering::Relaxed);
+    let fuzz = common::FUZZ.load(Ordering::Relaxed);
+
+    for (n, hunk) in patch.hunks.iter().enumerate() {
+        let nominal = nominal_start(hunk);
+        util::verbose(&tr!("Looking for hunk #{} near line {}...", n + 1, nominal));
+        // 使用最佳匹配算法定位 hunk 应用位置，不能回到已经输出过的行
+        let found = find_best_match(input, hunk, fuzz, whitespace, current_line);
+        let refused = found.filter(|p| strict && p.is_relaxed(hunk));
+
+        let (placement, result) = match found {
+            Some(p) if refused.is_none() => (p, None),
+            _ if is_already_applied(input, hunk) => {
+                results.push(unapplied(MergeResult::AlreadyApplied, nominal));
+                continue;
+            }
+            _ if merge => {
+                let at = nominal.max(current_line);
+                (Placement { at, lead: 0, trail: 0, whitespace: false }, Some(MergeResult::Conflict))
+            }
+            _ => {
+                let mut result = HunkResult {
+                    closest: Some(closest_match(input, hunk)),
+                    ..unapplied(MergeResult::Rejected, nominal)
+                };
+                if let Some(p) = refused {
+                    result.offset = p.offset(hunk);
+                    result.fuzz = p.fuzz();
+                    result.whitespace = p.whitespace;
+                    result.closest = Some(p.start());
+                }
+                results.push(result);
+                continue;
+            }
+        };
+        let where_to_apply = placement.at;
+
         // 输出 hunk 之前的未修改行
         while current_line < where_to_apply {
             if let Some(iline) = input.ifetch(current_line) {
-                outstate.write_line(iline.ptr);
+                outstate.write_text(iline.ptr, input.has_newline(current_line));
             }
             current_line += 1;
         }
-        
-        let res = merge_hunk(input, hunk, outstate, where_to_apply);
-        results.push(res);
-        
-        // 更新当前行位置：跳过 hunk 中处理的原始行数
-        // 计算 hunk 中的原始行数（context + remove）
-        let orig_lines = hunk.lines.iter().filter(|l| {
-            l.kind == LineKind::Context || l.kind == LineKind::Remove
-        }).count();
+
+        // 更新当前行位置：跳过 hunk 中处理的原始行数（context + remove）
+        let (result, orig_lines) = match result {
+            Some(conflict) => (conflict, write_conflict_hunk(input, hunk, outstate, where_to_apply)),
+            None => {
+                let hunk = trim_context(hunk, placement.lead, placement.trail);
+                let result = merge_hunk(input, &hunk, outstate, where_to_apply, placement.whitespace);
+                (result, hunk.lines.iter().filter(|l| l.kind != LineKind::Add).count())
+            }
+        };
         current_line = where_to_apply + orig_lines;
+
+        results.push(HunkResult {
+            result,
+            nominal,
+            applied_at: Some(placement.start()),
+            offset: placement.offset(hunk),
+            fuzz: placement.fuzz(),
+            whitespace: placement.whitespace,
+            reject_line: None,
+            closest: (result == MergeResult::Conflict).then(|| closest_match(input, hunk)),
+        });
     }
     
     // 输出最后一个 hunk 之后的所有剩余行
     while current_line <= input.num_lines() {
         if let Some(iline) = input.ifetch(current_line) {
-            outstate.write_line(iline.ptr);
+            outstate.write_text(iline.ptr, input.has_newline(current_line));
         }
         current_line += 1;
     }
@@ -168,13 +540,154 @@ pub fn merge_patch(
     results
 }
 
+/// 整个补丁无法应用（如要新建的文件已经存在）时，所有 hunk 都按被拒绝处理
+pub fn reject_all(patch: &Patch) -> Vec<HunkResult> {
+    patch.hunks.iter().map(|hunk| unapplied(MergeResult::Rejected, nominal_start(hunk))).collect()
+}
+
+/// 没有应用的 hunk 的结果
+fn unapplied(result: MergeResult, nominal: usize) -> HunkResult {
+    HunkResult {
+        result,
+        nominal,
+        applied_at: None,
+        offset: 0,
+        fuzz: 0,
+        whitespace: false,
+        reject_line: None,
+        closest: None,
+    }
+}
+
 // 假设 OutState 有写入接口
 impl OutState {
-    pub fn write_line(&mut self, line: &str) {
-        use std::io::Write;
-        // 直接写入内容，不添加额外的换行
-        write!(self.file, "{}\n", line).unwrap();
+    /// 写入一行新生成的文本（如 -D 的条件编译指令）
+    pub fn write_line(&mut self, line: &[u8]) {
+        self.write_new_text(line, true);
+    }
+
+    /// 写入一行来自补丁的新文本，行尾按目标文件的约定转换
+    ///
+    /// 指定 --binary 时不做任何转换，原样写入补丁中的字节。
+    pub fn write_new_text(&mut self, line: &[u8], newline: bool) {
+        if common::BINARY.load(Ordering::Relaxed) {
+            self.write_text(line, newline);
+            return;
+        }
+        let line = crate::util::strip_cr(line);
+        if newline && self.line_ending == LineEnding::CrLf {
+            let mut crlf = line.to_vec();
+            crlf.push(b'\r');
+            self.write_text(&crlf, newline);
+        } else {
+            self.write_text(line, newline);
+        }
+    }
+
+    /// 原样写入一行，newline 为 false 时不写行尾换行符（文件末尾没有换行符的行）
+    ///
+    /// 如果上一行没有写换行符，说明它其实不是最后一行，先补上换行符。
+    pub fn write_text(&mut self, line: &[u8], newline: bool) {
+        if !self.after_newline {
+            self.write_raw(b"\n");
+        }
+        self.write_raw(line);
+        if newline {
+            self.write_raw(b"\n");
+        }
         self.zero_output = false;
-        self.after_newline = true;
+        self.after_newline = newline;
+    }
+
+    /// 按目标文件原来的编码写出 UTF-8 字节，第一次写入时先写 BOM
+    fn write_raw(&mut self, utf8: &[u8]) {
+        if self.zero_output {
+            self.out.extend_from_slice(self.encoding.bom());
+            self.zero_output = false;
+        }
+        self.out.extend(self.encoding.encode(utf8));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pch::HunkLine;
+
+    /// 从 orig_start 开始的 hunk，行数按 lines 计算
+    fn hunk(orig_start: usize, lines: &[(LineKind, &str)]) -> PatchHunk {
+        let count = |skip| lines.iter().filter(|(kind, _)| *kind != skip).count();
+        PatchHunk {
+            orig_start,
+            orig_count: count(LineKind::Add),
+            new_start: orig_start,
+            new_count: count(LineKind::Remove),
+            lines: lines.iter()
+                .map(|&(kind, text)| HunkLine { kind, content: text.as_bytes().to_vec(), missing_newline: false })
+                .collect(),
+            func: None,
+            patch_line: 1,
+        }
+    }
+
+    #[test]
+    fn test_ifdef_regions_plain_groups() {
+        let rows = [
+            (LineKind::Context, b"a".as_slice(), true),
+            (LineKind::Remove, b"b".as_slice(), true),
+            (LineKind::Add, b"B".as_slice(), true),
+            (LineKind::Context, b"c".as_slice(), true),
+            (LineKind::Add, b"d".as_slice(), true),
+        ];
+        assert_eq!(ifdef_regions(&rows), vec![(1, 3), (4, 5)]);
+    }
+
+    #[test]
+    fn test_ifdef_regions_absorb_unbalanced_conditionals() {
+        // 删除了 #if 块的结尾，需要把前面的 #if 一起放进同一组
+        let rows = [
+            (LineKind::Context, b"#ifdef FOO".as_slice(), true),
+            (LineKind::Context, b"x".as_slice(), true),
+            (LineKind::Remove, b"#endif".as_slice(), true),
+            (LineKind::Add, b"y".as_slice(), true),
+            (LineKind::Add, b"#endif".as_slice(), true),
+            (LineKind::Context, b"z".as_slice(), true),
+        ];
+        assert_eq!(ifdef_regions(&rows), vec![(0, 5)]);
+    }
+
+    #[test]
+    fn test_closest_match_for_failed_hunk() {
+        let input = InputFile::from_bytes(b"a\nb\nc\nd\ne\nf\n", "test").unwrap();
+        let hunk = hunk(1, &[(LineKind::Context, "d"), (LineKind::Remove, "X"), (LineKind::Context, "f")]);
+        assert_eq!(find_best_match(&input, &hunk, 0, false, 1), None);
+        assert_eq!(closest_match(&input, &hunk), 4);
+    }
+
+    #[test]
+    fn test_hunk_status() {
+        let mut r = HunkResult { applied_at: Some(12), offset: 2, ..unapplied(MergeResult::Clean, 10) };
+        assert_eq!(r.status(), ExitStatus::Success);
+        r.result = MergeResult::AlreadyApplied;
+        assert_eq!(r.status(), ExitStatus::Failed);
+        r.result = MergeResult::Conflict;
+        assert_eq!(r.status(), ExitStatus::Failed);
+    }
+
+    #[test]
+    fn test_relaxed_placement() {
+        let input = InputFile::from_bytes(b"a\nb\nint  x;\nd\nQ\n", "test").unwrap();
+        let hunk = hunk(2, &[
+            (LineKind::Context, "b"),
+            (LineKind::Remove, "int x;"),
+            (LineKind::Add, "int y;"),
+            (LineKind::Context, "d"),
+            (LineKind::Context, "e"),
+        ]);
+        assert_eq!(find_best_match(&input, &hunk, 2, false, 1), None);
+        let p = find_best_match(&input, &hunk, 2, true, 1).unwrap();
+        assert_eq!((p.at, p.start(), p.fuzz(), p.whitespace), (3, 2, 1, true));
+        assert_eq!(p.offset(&hunk), 0);
+        assert_eq!(trim_context(&hunk, p.lead, p.trail).lines.len(), 3);
     }
 }
\ No newline at end of file
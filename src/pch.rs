@@ -1,18 +1,13 @@
-use std::fs::File;
+use std::borrow::Cow;
+use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::sync::atomic::Ordering;
 
-/// Patch diff类型
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum DiffType {
-    NoDiff,
-    ContextDiff,
-    NormalDiff,
-    EdDiff,
-    NewContextDiff,
-    UniDiff,
-    GitBinaryDiff,
-}
+use crate::binary::{self, BinaryPatch};
+use crate::common::{self, DiffType};
+use crate::ed::{self, EdCommand};
+use crate::i18n::tr;
+use crate::util::{self, strip_cr};
 
 /// Patch头部信息
 #[derive(Debug, Clone)]
@@ -26,6 +21,19 @@ pub struct PatchHeader {
     pub new_mode: Option<u32>,
     pub sha1_old: Option<String>,
     pub sha1_new: Option<String>,
+    /// "diff --git" 格式的补丁
+    pub git_diff: bool,
+    /// git 扩展头中的重命名（rename from/to）或复制（copy from/to）
+    pub operation: Option<GitOperation>,
+    /// git 扩展头 "similarity index" 中的相似度百分比
+    pub similarity: Option<u32>,
+}
+
+/// git 扩展头描述的文件操作：把旧文件改名或复制为新文件，再应用 hunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GitOperation {
+    Rename,
+    Copy,
 }
 
 /// 一个hunk块
@@ -37,13 +45,18 @@ pub struct PatchHunk {
     pub new_count: usize,
     pub lines: Vec<HunkLine>,
     pub func: Option<String>,
+    /// hunk 在补丁文件中开始的行号（1-based），用于诊断信息
+    pub patch_line: usize,
 }
 
 /// hunk中的一行
 #[derive(Debug, Clone)]
 pub struct HunkLine {
     pub kind: LineKind,
-    pub content: String,
+    /// 行内容的原始字节，不做任何编码转换
+    pub content: Vec<u8>,
+    /// 后面跟着 "\ No newline at end of file"，即该行是文件末尾且没有换行符
+    pub missing_newline: bool,
 }
 
 /// 行类型
@@ -60,14 +73,61 @@ pub struct Patch {
     pub diff_type: DiffType,
     pub header: PatchHeader,
     pub hunks: Vec<PatchHunk>,
+    /// ed 脚本格式的命令，仅在 diff_type 为 EdDiff 时使用
+    pub ed_commands: Vec<EdCommand>,
+    /// 第一个 hunk 之前的文本（文件头以及前面的说明），--verbose 时显示
+    pub leading_text: Vec<Vec<u8>>,
+    /// git 二进制补丁，仅在 diff_type 为 GitBinaryDiff 时使用
+    pub binary: Option<BinaryPatch>,
+}
+
+/// 补丁文件中的全部补丁，每个目标文件一个
+#[derive(Debug, Clone)]
+pub struct PatchSet {
+    pub patches: Vec<Patch>,
+}
+
+impl PatchSet {
+    /// 读取并解析补丁文件，path 为 None 或 "-" 时从标准输入读取
+    pub fn from_file(path: Option<&str>) -> Result<Self, String> {
+        let lines = match path {
+            None | Some("-") => read_patch_lines(std::io::stdin().lock()),
+            Some(path) => {
+                let file = File::open(path).map_err(|e| tr!("can't open patch file: {}", e))?;
+                read_patch_lines(BufReader::new(file))
+            }
+        };
+        let lines = lines.map_err(|e| tr!("can't read patch file: {}", e))?;
+        Self::from_lines(&lines)
+    }
+
+    /// 依次解析每个文件的补丁，跳过没有任何改动的部分（如邮件正文）
+    pub fn from_lines(lines: &[Vec<u8>]) -> Result<Self, String> {
+        let mut patches = Vec::new();
+        let mut i = 0;
+        while i < lines.len() {
+            let (patch, consumed) = Patch::parse(lines, i)?;
+            if patch.has_changes() {
+                patches.push(patch);
+            }
+            i += consumed.max(1);
+        }
+        Ok(PatchSet { patches })
+    }
+
+    /// -R：把每个文件的补丁反过来应用
+    pub fn reverse(&mut self) -> Result<(), String> {
+        self.patches.iter_mut().try_for_each(Patch::reverse)
+    }
 }
 
 /// 解析patch文件
 impl Patch {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
-        let file = File::open(path).map_err(|e| format!("打开patch文件失败: {}", e))?;
-        let reader = BufReader::new(file);
-
+    /// 从第 start_idx 行开始解析一个文件的补丁，返回(patch, consumed_lines)
+    ///
+    /// 已经读到 hunk 之后再遇到新的文件头（"diff "、"Index: "、"*** " 或
+    /// unified 的 "--- "），说明下一个文件的补丁开始了，在这里停下。
+    fn parse(lines: &[Vec<u8>], start_idx: usize) -> Result<(Self, usize), String> {
         let mut hunks = Vec::new();
         let mut header = PatchHeader {
             old_file: None,
@@ -79,51 +139,99 @@ impl Patch {
             new_mode: None,
             sha1_old: None,
             sha1_new: None,
+            git_diff: false,
+            operation: None,
+            similarity: None,
         };
 
         let mut diff_type = DiffType::NoDiff;
+        let mut ed_commands = Vec::new();
+        let mut binary = None;
+        let force_ed = common::DIFF_TYPE.load(Ordering::Relaxed) == DiffType::EdDiff as i32;
+        let mut body_start = None;
 
-        let lines: Vec<String> = reader.lines()
-            .filter_map(|l| l.ok())
-            .collect();
-        
-        let mut i = 0;
+        let mut i = start_idx;
         while i < lines.len() {
-            let line = &lines[i];
-            if line.starts_with("*** ") {
+            let line = text(&lines[i]);
+            let has_changes = !hunks.is_empty() || !ed_commands.is_empty() || binary.is_some();
+            if has_changes && is_file_header(&line, diff_type) {
+                break;
+            }
+            if let Some(names) = line.strip_prefix("diff --git ") {
+                // 没有 hunk 的 git 补丁（如单纯的重命名）到下一个 "diff --git" 为止
+                if header.git_diff {
+                    break;
+                }
+                header.git_diff = true;
+                if let Some((old, new)) = parse_git_names(names) {
+                    header.old_file = Some(old);
+                    header.new_file = Some(new);
+                }
+            } else if let Some(name) = line.strip_prefix("Index: ") {
+                header.index_file = Some(name.trim().to_string());
+            } else if header.git_diff && body_start.is_none() && parse_git_extended_header(&line, &mut header) {
+                // 已经记录在 header 中
+            } else if line == "GIT binary patch" {
+                let (patch, lines_consumed) = binary::parse_binary_patch(lines, i + 1)?;
+                body_start.get_or_insert(i);
+                diff_type = DiffType::GitBinaryDiff;
+                binary = Some(patch);
+                i += lines_consumed + 1;
+                continue;
+            } else if force_ed || ed::is_ed_command(&line) {
+                // ed 脚本格式 (e.g., "3a", "2,4d", "5c")
+                let (commands, lines_consumed) = ed::parse_ed_script(lines, i)?;
+                if !commands.is_empty() {
+                    body_start.get_or_insert(i);
+                    diff_type = DiffType::EdDiff;
+                    ed_commands.extend(commands);
+                }
+                i += lines_consumed.max(1);
+                continue;
+            } else if line.starts_with("*** ") {
                 // Context diff format
-                header.old_file = Some(extract_filename(line));
+                header.old_file = Some(extract_filename(&line));
                 diff_type = DiffType::ContextDiff;
-            } else if line.starts_with("--- ") && diff_type == DiffType::ContextDiff {
+            } else if line.starts_with("--- ") && matches!(diff_type, DiffType::ContextDiff | DiffType::NewContextDiff) {
                 // Second header line in context diff
-                header.new_file = Some(extract_filename(line));
+                header.new_file = Some(extract_filename(&line));
             } else if line.starts_with("--- ") {
-                // Unified diff format
-                header.old_file = Some(extract_filename(line));
+                // Unified diff format；git 补丁的文件名以 "diff --git" 和扩展头为准
+                if !header.git_diff || header.old_file.is_none() {
+                    header.old_file = Some(extract_filename(&line));
+                }
             } else if line.starts_with("+++ ") {
-                header.new_file = Some(extract_filename(line));
+                if !header.git_diff || header.new_file.is_none() {
+                    header.new_file = Some(extract_filename(&line));
+                }
             } else if line.starts_with("***************") {
                 // Context diff hunk separator
-                diff_type = DiffType::ContextDiff;
-                if i + 1 < lines.len() {
-                    let (hunk, lines_consumed) = parse_context_hunk_from_vec(&lines, i + 1)?;
-                    hunks.push(hunk);
-                    i += lines_consumed;
-                    continue;
+                let (hunk, lines_consumed, new_style) = parse_context_hunk_from_vec(lines, i)?;
+                body_start.get_or_insert(i);
+                if new_style {
+                    diff_type = DiffType::NewContextDiff;
+                } else if diff_type != DiffType::NewContextDiff {
+                    diff_type = DiffType::ContextDiff;
                 }
+                hunks.push(hunk);
+                i += lines_consumed;
+                continue;
             } else if line.starts_with("@@ ") {
                 diff_type = DiffType::UniDiff;
-                let (hunk, lines_consumed) = parse_unified_hunk_from_vec(&lines, i)?;
+                let (hunk, lines_consumed) = parse_unified_hunk_from_vec(lines, i)?;
+                body_start.get_or_insert(i);
                 hunks.push(hunk);
                 i += lines_consumed;
                 continue;
             } else if line.contains('c') || line.contains('a') || line.contains('d') {
                 // Normal diff format (e.g., "3c3", "2,4d1", "1a2,3")
                 // Check if it looks like a normal diff command
-                let parts: Vec<&str> = line.split(|c: char| c == 'a' || c == 'c' || c == 'd').collect();
-                if parts.len() == 2 && parts[0].chars().all(|c| c.is_numeric() || c == ',') {
+                let parts: Vec<&str> = line.split(['a', 'c', 'd']).collect();
+                let is_range = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_numeric() || c == ',');
+                if parts.len() == 2 && is_range(parts[0]) && is_range(parts[1].trim_end()) {
                     diff_type = DiffType::NormalDiff;
-                    let (hunk, lines_consumed) = parse_normal_hunk_from_vec(&lines, i)?;
+                    let (hunk, lines_consumed) = parse_normal_hunk_from_vec(lines, i)?;
+                    body_start.get_or_insert(i);
                     hunks.push(hunk);
                     i += lines_consumed;
                     continue;
@@ -132,75 +240,342 @@ impl Patch {
             i += 1;
         }
 
-        Ok(Patch {
+        Ok((Patch {
             diff_type,
             header,
             hunks,
-        })
+            ed_commands,
+            leading_text: lines[start_idx..body_start.unwrap_or(start_idx)].to_vec(),
+            binary,
+        }, i - start_idx))
+    }
+
+    /// 按 -p 的设置去掉前缀后的候选文件名：旧文件名、新文件名、Index 中的文件名
+    pub fn candidate_files(&self) -> Vec<String> {
+        let strip = common::STRIP.load(Ordering::Relaxed);
+        [&self.header.old_file, &self.header.new_file, &self.header.index_file]
+            .into_iter()
+            .flatten()
+            .filter(|name| name.as_str() != "/dev/null")
+            .filter_map(|name| strip_leading_components(name, strip))
+            .collect()
+    }
+
+    /// 选出要修补的文件：第一个存在的候选文件（符号链接本身存在即可）；
+    /// 新建文件时为新文件名，重命名或复制时为新文件名
+    pub fn target_file(&self) -> Option<String> {
+        if self.header.operation.is_some() {
+            let strip = common::STRIP.load(Ordering::Relaxed);
+            return self.header.new_file.as_deref().and_then(|name| strip_leading_components(name, strip));
+        }
+        let mut candidates = self.candidate_files().into_iter();
+        if self.creates_file() {
+            return candidates.next();
+        }
+        candidates.find(|name| fs::symlink_metadata(name).is_ok())
+    }
+
+    /// 重命名或复制时的源文件名
+    pub fn source_file(&self) -> Option<String> {
+        self.header.operation?;
+        let strip = common::STRIP.load(Ordering::Relaxed);
+        self.header.old_file.as_deref().and_then(|name| strip_leading_components(name, strip))
+    }
+
+    /// 从补丁中的文件名得到 target 时实际去掉的路径组件数
+    ///
+    /// 没有 -p 时只保留文件名本身，这里换算成去掉的组件数；
+    /// target 不是从补丁中的文件名得到的（如命令行上直接指定）时返回 None。
+    pub fn strip_level(&self, target: &str) -> Option<usize> {
+        let strip = common::STRIP.load(Ordering::Relaxed);
+        [&self.header.old_file, &self.header.new_file, &self.header.index_file]
+            .into_iter()
+            .flatten()
+            .filter(|name| name.as_str() != "/dev/null")
+            .find_map(|name| {
+                (0..)
+                    .map_while(|n| strip_leading_components(name, n).map(|stripped| (n, stripped)))
+                    .find(|(n, stripped)| stripped == target && (strip < 0 || *n == strip))
+                    .map(|(n, _)| n as usize)
+            })
+    }
+
+    /// 补丁是否有任何改动：hunk、ed 命令、二进制补丁，或者 git 扩展头中的文件操作、
+    /// 权限变化以及新建、删除（空）文件
+    pub fn has_changes(&self) -> bool {
+        !self.hunks.is_empty()
+            || !self.ed_commands.is_empty()
+            || self.binary.is_some()
+            || self.header.operation.is_some()
+            || self.mode_change().is_some()
+            || (self.header.git_diff && (self.creates_file() || self.deletes_file()))
+    }
+
+    /// 补丁是否修改符号链接（git 补丁中权限为 120000，内容为链接指向的路径）
+    pub fn is_symlink(&self) -> bool {
+        [self.header.old_mode, self.header.new_mode].contains(&Some(util::SYMLINK_MODE))
+    }
+
+    /// git 补丁中的权限变化：(旧权限, 新权限)
+    pub fn mode_change(&self) -> Option<(u32, u32)> {
+        match (self.header.old_mode, self.header.new_mode) {
+            (Some(old), Some(new)) if old != new => Some((old, new)),
+            _ => None,
+        }
+    }
+
+    /// 补丁是否新建文件（旧文件名为 /dev/null）
+    pub fn creates_file(&self) -> bool {
+        self.header.old_file.as_deref() == Some("/dev/null")
+    }
+
+    /// 补丁是否删除文件（新文件名为 /dev/null）
+    pub fn deletes_file(&self) -> bool {
+        self.header.new_file.as_deref() == Some("/dev/null")
+    }
+
+    /// 补丁头中出现的文件名，用于提示
+    pub fn file_names(&self) -> String {
+        [&self.header.old_file, &self.header.new_file, &self.header.index_file]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// 交换补丁的新旧两边：文件名、时间、权限、hash 以及每个 hunk 的新增和删除
+    ///
+    /// 二进制补丁换用 git 为反向应用附上的一段。ed 脚本和复制无法反过来应用。
+    pub fn reverse(&mut self) -> Result<(), String> {
+        if !self.ed_commands.is_empty() {
+            return Err(tr!("ed scripts can't be applied in reverse ({})", self.file_names()));
+        }
+        if self.header.operation == Some(GitOperation::Copy) {
+            return Err(tr!("copies can't be applied in reverse ({})", self.file_names()));
+        }
+        if let Some(binary) = &mut self.binary
+            && !binary.reverse()
+        {
+            return Err(tr!("binary patch has no reverse section ({})", self.file_names()));
+        }
+        let header = &mut self.header;
+        std::mem::swap(&mut header.old_file, &mut header.new_file);
+        std::mem::swap(&mut header.old_time, &mut header.new_time);
+        std::mem::swap(&mut header.old_mode, &mut header.new_mode);
+        std::mem::swap(&mut header.sha1_old, &mut header.sha1_new);
+        for hunk in &mut self.hunks {
+            std::mem::swap(&mut hunk.orig_start, &mut hunk.new_start);
+            std::mem::swap(&mut hunk.orig_count, &mut hunk.new_count);
+            for line in &mut hunk.lines {
+                line.kind = match line.kind {
+                    LineKind::Add => LineKind::Remove,
+                    LineKind::Remove => LineKind::Add,
+                    LineKind::Context => LineKind::Context,
+                };
+            }
+            // 每组改动中仍然先删除后新增
+            for change in hunk.lines.split_mut(|l| l.kind == LineKind::Context) {
+                change.sort_by_key(|l| l.kind == LineKind::Add);
+            }
+        }
+        Ok(())
     }
 }
 
+/// 判断一行是否是下一个文件的补丁头
+fn is_file_header(line: &str, diff_type: DiffType) -> bool {
+    line.starts_with("diff ")
+        || line.starts_with("Index: ")
+        || (line.starts_with("*** ") && !line.starts_with("***************"))
+        || (line.starts_with("--- ") && !matches!(diff_type, DiffType::ContextDiff | DiffType::NewContextDiff))
+}
+
+/// 解析 "diff --git a/old b/new" 中的两个文件名
+///
+/// 文件名中可能有空格，这时取两边去掉第一个路径组件后相同的分法
+/// （没有改名的情况），否则在 " b/" 处分开；都不行时返回 None。
+fn parse_git_names(names: &str) -> Option<(String, String)> {
+    let names = names.trim_end();
+    if let [old, new] = names.split(' ').collect::<Vec<_>>()[..] {
+        return Some((old.to_string(), new.to_string()));
+    }
+    names.match_indices(' ').find_map(|(i, _)| {
+        let (old, new) = (&names[..i], &names[i + 1..]);
+        let same = strip_leading_components(old, 1).is_some_and(|o| strip_leading_components(new, 1) == Some(o));
+        same.then(|| (old.to_string(), new.to_string()))
+    })
+    .or_else(|| {
+        let (old, new) = names.strip_prefix("a/")?.split_once(" b/")?;
+        Some((format!("a/{}", old), format!("b/{}", new)))
+    })
+}
+
+/// 解析 git 扩展头中的一行，记录到 header 中；不是扩展头时返回 false
+///
+/// rename/copy 行中的文件名没有 a/、b/ 前缀，所以以 "diff --git" 行中的为准
+/// （受 -p 影响）；只有 "diff --git" 行无法分成两个文件名时，才补上 git 默认的前缀
+/// 改用这里的文件名。新建、删除文件时与 "--- /dev/null" 一样记下 /dev/null，
+/// 这样没有 hunk 的空文件也能新建和删除。
+fn parse_git_extended_header(line: &str, header: &mut PatchHeader) -> bool {
+    let mode = |s: &str| u32::from_str_radix(s.trim(), 8).ok();
+    let operation = if line.starts_with("rename ") { GitOperation::Rename } else { GitOperation::Copy };
+    if let Some(name) = line.strip_prefix("rename from ").or_else(|| line.strip_prefix("copy from ")) {
+        header.operation = Some(operation);
+        header.old_file.get_or_insert_with(|| format!("a/{}", name.trim_end()));
+    } else if let Some(name) = line.strip_prefix("rename to ").or_else(|| line.strip_prefix("copy to ")) {
+        header.operation = Some(operation);
+        header.new_file.get_or_insert_with(|| format!("b/{}", name.trim_end()));
+    } else if let Some(percent) = line.strip_prefix("similarity index ") {
+        header.similarity = percent.trim().trim_end_matches('%').parse().ok();
+    } else if let Some(m) = line.strip_prefix("old mode ") {
+        header.old_mode = mode(m);
+    } else if let Some(m) = line.strip_prefix("new mode ") {
+        header.new_mode = mode(m);
+    } else if let Some(m) = line.strip_prefix("new file mode ") {
+        header.new_mode = mode(m);
+        header.old_file = Some("/dev/null".to_string());
+    } else if let Some(m) = line.strip_prefix("deleted file mode ") {
+        header.old_mode = mode(m);
+        header.new_file = Some("/dev/null".to_string());
+    } else if let Some(rest) = line.strip_prefix("index ") {
+        // "index abc..def 100644"：权限没有变化时写在 index 行上
+        let mut fields = rest.split_whitespace();
+        if let Some((old, new)) = fields.next().and_then(|hashes| hashes.split_once("..")) {
+            header.sha1_old = Some(old.to_string());
+            header.sha1_new = Some(new.to_string());
+        }
+        if let Some(m) = fields.next().and_then(mode) {
+            header.old_mode.get_or_insert(m);
+            header.new_mode.get_or_insert(m);
+        }
+    } else {
+        return line.starts_with("dissimilarity index ");
+    }
+    true
+}
+
+/// 去掉文件名开头的 strip 个路径组件（-p NUM），strip 为负数时只保留文件名本身
+///
+/// 组件数不够时返回 None，和 GNU patch 一样不把它当作候选文件。
+pub fn strip_leading_components(name: &str, strip: i32) -> Option<String> {
+    if strip < 0 {
+        return name.rsplit('/').find(|c| !c.is_empty()).map(str::to_string);
+    }
+    let mut rest = name;
+    for _ in 0..strip {
+        let (_, tail) = rest.split_once('/')?;
+        rest = tail.trim_start_matches('/');
+    }
+    (!rest.is_empty()).then(|| rest.to_string())
+}
+
+
+/// 按行读取补丁文件，每行保留原始字节（去掉行尾的 '\n'）
+///
+/// CRLF 补丁中内容行的 '\r' 也会保留，是否忽略它由应用时的比较决定。
+fn read_patch_lines<R: BufRead>(mut reader: R) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut lines = Vec::new();
+    let mut buf = Vec::new();
+    while reader.read_until(b'\n', &mut buf)? > 0 {
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+        }
+        lines.push(std::mem::take(&mut buf));
+    }
+    Ok(lines)
+}
+
+/// 把补丁中的结构行（文件头、hunk 头等）转换为文本，非 UTF-8 字节只影响显示
+fn text(line: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(strip_cr(line))
+}
+
 /// 提取文件名
+///
+/// "--- oldfile\t2025-10-01 ..." 中文件名到 tab 为止，可以含有空格；
+/// 没有 tab 时文件名到第一个空白为止。
 fn extract_filename(line: &str) -> String {
-    // "--- oldfile\t2025-10-01 ..." 取第一个空格后到第一个tab或换行
-    let s = line.trim_start_matches(|c| c == '-' || c == '+').trim();
-    s.split('\t').next().unwrap_or("").split_whitespace().next().unwrap_or("").to_string()
+    let s = line.trim_start_matches(['-', '+', '*']).trim_start();
+    match s.split_once('\t') {
+        Some((name, _)) => name.trim_end().to_string(),
+        None => s.split_whitespace().next().unwrap_or("").to_string(),
+    }
 }
 
-/// 从行向量解析unified diff的hunk块，返回(hunk, consumed_lines)
-fn parse_unified_hunk_from_vec(lines: &[String], start_idx: usize) -> Result<(PatchHunk, usize), String> {
-    if start_idx >= lines.len() {
-        return Err("Invalid hunk start index".to_string());
+/// 解析 unified diff 的范围 "N,M" 或 "N"（省略行数时为 1），返回(起始行, 行数)
+fn parse_unified_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((s, c)) => Some((s.parse().ok()?, c.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
     }
-    
-    let first_line = &lines[start_idx];
-    
+}
+
+/// 从行向量解析unified diff的hunk块，返回(hunk, consumed_lines)
+///
+/// 与 GNU patch 和 git 一样按 "@@" 头中的行数读取 hunk 内容，因此内容以
+/// "---"、"+++" 或 "@@" 开头的行（如被删除的 "-- comment"）不会提前结束 hunk。
+/// hunk 中的空行按空白上下文行处理。
+fn parse_unified_hunk_from_vec(lines: &[Vec<u8>], start_idx: usize) -> Result<(PatchHunk, usize), String> {
+    let first_line = text(&lines[start_idx]);
+    let bad_header = || tr!("line {}: malformed unified diff hunk header: {}", start_idx + 1, first_line);
+
     // 头形如: "@@ -1,5 +1,6 @@ 函数名"
-    let mut orig_start = 0;
-    let mut orig_count = 0;
-    let mut new_start = 0;
-    let mut new_count = 0;
-    let mut func_name = None;
-
-    let mut parts = first_line.split("@@").nth(1).unwrap_or("").trim().split(' ');
-    if let Some(ranges) = parts.next() {
-        let mut rng_parts = ranges.split('+');
-        if let Some(orig) = rng_parts.next() {
-            let o = orig.trim_start_matches('-');
-            let mut n = o.split(',');
-            orig_start = n.next().unwrap_or("1").parse().unwrap_or(1);
-            orig_count = n.next().unwrap_or("1").parse().unwrap_or(1);
+    let rest = first_line.strip_prefix("@@ -").ok_or_else(bad_header)?;
+    let (ranges, func) = rest.split_once(" @@").ok_or_else(bad_header)?;
+    let (orig, new_) = ranges.split_once(" +").ok_or_else(bad_header)?;
+    let (orig_start, orig_count) = parse_unified_range(orig).ok_or_else(bad_header)?;
+    let (new_start, new_count) = parse_unified_range(new_).ok_or_else(bad_header)?;
+    let func = func.trim();
+    let func_name = (!func.is_empty()).then(|| func.to_string());
+
+    let mut hunk_lines: Vec<HunkLine> = Vec::new();
+    let mut orig_left = orig_count;
+    let mut new_left = new_count;
+    let mut i = start_idx + 1;
+
+    while orig_left > 0 || new_left > 0 {
+        let Some(line) = lines.get(i) else {
+            return Err(tr!(
+                "line {}: unexpected end of hunk, {} old and {} new lines missing",
+                start_idx + 1, orig_left, new_left
+            ));
+        };
+        let mismatch = || tr!("line {}: hunk does not match the line counts in its @@ header: {}", i + 1, text(line));
+
+        let (kind, content) = match strip_cr(line).first() {
+            None => (LineKind::Context, Vec::new()),
+            Some(b' ') => (LineKind::Context, line[1..].to_vec()),
+            Some(b'-') => (LineKind::Remove, line[1..].to_vec()),
+            Some(b'+') => (LineKind::Add, line[1..].to_vec()),
+            Some(b'\\') => {
+                // "\ No newline at end of file"
+                if let Some(last) = hunk_lines.last_mut() {
+                    last.missing_newline = true;
+                }
+                i += 1;
+                continue;
+            }
+            Some(_) => return Err(mismatch()),
+        };
+        if kind != LineKind::Add {
+            orig_left = orig_left.checked_sub(1).ok_or_else(mismatch)?;
         }
-        if let Some(new_) = rng_parts.next() {
-            let n = new_.split(',');
-            let mut n_iter = n;
-            new_start = n_iter.next().unwrap_or("1").parse().unwrap_or(1);
-            new_count = n_iter.next().unwrap_or("1").parse().unwrap_or(1);
+        if kind != LineKind::Remove {
+            new_left = new_left.checked_sub(1).ok_or_else(mismatch)?;
         }
+        hunk_lines.push(HunkLine { kind, content, missing_newline: false });
+        i += 1;
     }
-    func_name = parts.next().map(|s| s.trim().to_string());
 
-    let mut hunk_lines = Vec::new();
-    let mut i = start_idx + 1;
-    
-    while i < lines.len() {
-        let line = &lines[i];
-        
-        // 如果遇到新的hunk或者文件头，停止当前hunk的解析
-        if line.starts_with("@@") || line.starts_with("---") || line.starts_with("+++") {
-            break;
-        }
-        
-        let c = line.chars().next().unwrap_or(' ');
-        match c {
-            '+' => hunk_lines.push(HunkLine { kind: LineKind::Add, content: line[1..].to_string() }),
-            '-' => hunk_lines.push(HunkLine { kind: LineKind::Remove, content: line[1..].to_string() }),
-            ' ' => hunk_lines.push(HunkLine { kind: LineKind::Context, content: line[1..].to_string() }),
-            _ => {} // 忽略其他行（如注释等）
+    // 紧跟在最后一行之后的 "\ No newline at end of file"
+    if lines.get(i).is_some_and(|l| l.starts_with(b"\\")) {
+        if let Some(last) = hunk_lines.last_mut() {
+            last.missing_newline = true;
         }
         i += 1;
     }
-    
+
     let consumed = i - start_idx;
 
     Ok((PatchHunk {
@@ -210,125 +585,204 @@ fn parse_unified_hunk_from_vec(lines: &[String], start_idx: usize) -> Result<(Pa
         new_count,
         lines: hunk_lines,
         func: func_name,
+        patch_line: start_idx + 1,
     }, consumed))
 }
 
-/// 从行向量解析context diff的hunk块，返回(hunk, consumed_lines)
-fn parse_context_hunk_from_vec(lines: &[String], start_idx: usize) -> Result<(PatchHunk, usize), String> {
-    if start_idx >= lines.len() {
-        return Err("Invalid hunk start index".to_string());
+/// 解析 context diff 的范围 "N,M" 或 "N"，返回(起始行, 行数)
+///
+/// 单个数字 0 表示空范围（如在文件开头插入时的 "*** 0 ****"）。
+fn parse_context_range(range: &str) -> Result<(usize, usize), String> {
+    let bad = || tr!("malformed context diff range: {}", range);
+    match range.trim().split_once(',') {
+        Some((s, e)) => {
+            let start: usize = s.parse().map_err(|_| bad())?;
+            let end: usize = e.parse().map_err(|_| bad())?;
+            if end + 1 < start {
+                return Err(bad());
+            }
+            Ok((start, end + 1 - start))
+        }
+        None => {
+            let start: usize = range.trim().parse().map_err(|_| bad())?;
+            Ok((start, if start == 0 { 0 } else { 1 }))
+        }
     }
-    
+}
+
+/// 判断一行是否可以作为 context diff 某一节中的内容行
+///
+/// 空行视为被编辑器或邮件客户端去掉了行尾空格的空白上下文行。
+fn is_context_section_line(line: &[u8], new_section: bool) -> bool {
+    let marker: &[u8] = if new_section { b"+ " } else { b"- " };
+    strip_cr(line).is_empty()
+        || line.starts_with(b"  ")
+        || line.starts_with(b"! ")
+        || line.starts_with(marker)
+        || strip_cr(line) == b" "
+        || line.starts_with(b"\\")
+}
+
+/// context diff 一节中的一行：(标记字符, 内容, 是否缺少行尾换行符)
+type SectionLine = (u8, Vec<u8>, bool);
+
+/// 读取 context diff 的一节（旧文本或新文本），返回(行列表, 下一行的下标)
+fn read_context_section(
+    lines: &[Vec<u8>],
+    mut i: usize,
+    count: usize,
+    new_section: bool,
+) -> (Vec<SectionLine>, usize) {
+    let mut section: Vec<SectionLine> = Vec::new();
+    while i < lines.len() && section.len() < count {
+        let line = &lines[i];
+        if !is_context_section_line(line, new_section) {
+            break;
+        }
+        if line.starts_with(b"\\") {
+            if let Some(last) = section.last_mut() {
+                last.2 = true;
+            }
+            i += 1;
+            continue;
+        }
+        let tag = line.first().copied().unwrap_or(b' ');
+        let content = line.get(2..).unwrap_or_default().to_vec();
+        section.push((tag, content, false));
+        i += 1;
+    }
+    // 本节最后一行之后的 "\ No newline at end of file"
+    if lines.get(i).is_some_and(|l| l.starts_with(b"\\")) {
+        if let Some(last) = section.last_mut() {
+            last.2 = true;
+        }
+        i += 1;
+    }
+    (section, i)
+}
+
+/// 从行向量解析context diff的hunk块，返回(hunk, consumed_lines, 是否省略了某一节)
+///
+/// start_idx 指向 "***************" 分隔行。新式 context diff 会省略没有改动的
+/// 一节（纯插入时省略旧文本，纯删除时省略新文本），此时该节的内容由另一节的
+/// 上下文行推出；两节都存在时按上下文行把 '!' 改动组对齐。
+fn parse_context_hunk_from_vec(lines: &[Vec<u8>], start_idx: usize) -> Result<(PatchHunk, usize, bool), String> {
     // Context diff 格式:
+    // ***************
     // *** 1,5 ****  <- 原始范围
     //   context
     // ! changed
     // - removed
-    // --- 1,5 ----  <- 新范围  
+    // --- 1,5 ----  <- 新范围
     //   context
     // ! changed
     // + added
-    
-    let first_line = &lines[start_idx];
-    let mut orig_start = 0;
-    let mut orig_count = 0;
-    
-    // 解析 "*** 1,5 ****"
-    if first_line.starts_with("*** ") {
-        let range_str = first_line.trim_start_matches("*** ").trim_end_matches(" ****");
-        let mut parts = range_str.split(',');
-        orig_start = parts.next().unwrap_or("1").parse().unwrap_or(1);
-        orig_count = parts.next().unwrap_or("1").parse().unwrap_or(1);
-    }
-    
-    // 读取原始部分的行
-    let mut orig_lines = Vec::new();
+
+    let func = text(&lines[start_idx]);
+    let func = func
+        .trim_start_matches('*')
+        .trim();
+    let func = (!func.is_empty()).then(|| func.to_string());
+
     let mut i = start_idx + 1;
-    while i < lines.len() {
-        let line = &lines[i];
-        if line.starts_with("---") {
-            break;
-        }
-        
-        let c = line.chars().next().unwrap_or(' ');
-        match c {
-            ' ' => orig_lines.push((LineKind::Context, line[2..].to_string())),
-            '!' => orig_lines.push((LineKind::Remove, line[2..].to_string())),
-            '-' => orig_lines.push((LineKind::Remove, line[2..].to_string())),
-            '+' => {}, // + 只出现在新部分
-            _ => {}
-        }
-        i += 1;
+    let old_header = text(lines.get(i).ok_or(tr!("unexpected end of context diff hunk"))?);
+    let old_range = old_header
+        .strip_prefix("*** ")
+        .and_then(|r| r.strip_suffix(" ****"))
+        .ok_or_else(|| tr!("context diff hunk is missing the old range: {}", old_header))?;
+    let (orig_start, orig_count) = parse_context_range(old_range)?;
+    i += 1;
+
+    // 旧文本一节，可能被省略
+    let (old_lines, next) = read_context_section(lines, i, orig_count, false);
+    i = next;
+
+    let new_header = text(lines.get(i).ok_or(tr!("unexpected end of context diff hunk"))?);
+    let new_range = new_header
+        .strip_prefix("--- ")
+        .and_then(|r| r.strip_suffix(" ----"))
+        .ok_or_else(|| tr!("context diff hunk is missing the new range: {}", new_header))?;
+    let (new_start, new_count) = parse_context_range(new_range)?;
+    i += 1;
+
+    // 新文本一节；只有在旧文本里没有 '!' 时才可能被省略
+    let old_has_change = old_lines.iter().any(|l| l.0 == b'!');
+    let new_present = old_has_change
+        || lines.get(i).is_some_and(|l| {
+            is_context_section_line(l, true)
+                && (!strip_cr(l).is_empty()
+                    || lines[i..].len() >= new_count
+                        && lines[i..i + new_count].iter().all(|l| is_context_section_line(l, true)))
+        });
+    let new_lines = if new_present {
+        let (section, next) = read_context_section(lines, i, new_count, true);
+        i = next;
+        section
+    } else {
+        Vec::new()
+    };
+
+    let old_omitted = old_lines.is_empty() && orig_count > 0;
+    let new_omitted = new_lines.is_empty() && new_count > 0;
+    if old_omitted && new_omitted {
+        return Err(tr!("context diff hunk has two empty sections: {}", old_header));
     }
-    
-    // 解析 "--- 1,5 ----"
-    let mut new_start = 0;
-    let mut new_count = 0;
-    if i < lines.len() && lines[i].starts_with("---") {
-        let range_str = lines[i].trim_start_matches("--- ").trim_end_matches(" ----");
-        let mut parts = range_str.split(',');
-        new_start = parts.next().unwrap_or("1").parse().unwrap_or(1);
-        new_count = parts.next().unwrap_or("1").parse().unwrap_or(1);
-        i += 1;
+    if !old_omitted && old_lines.len() != orig_count {
+        return Err(tr!("context diff hunk old lines do not match range {}", old_range));
     }
-    
-    // 读取新部分的行
-    let mut new_lines = Vec::new();
-    while i < lines.len() {
-        let line = &lines[i];
-        if line.starts_with("***") || line.is_empty() {
-            break;
-        }
-        
-        let c = line.chars().next().unwrap_or(' ');
-        match c {
-            ' ' => new_lines.push((LineKind::Context, line[2..].to_string())),
-            '!' => new_lines.push((LineKind::Add, line[2..].to_string())),
-            '+' => new_lines.push((LineKind::Add, line[2..].to_string())),
-            '-' => {}, // - 只出现在原始部分
-            _ => {}
-        }
-        i += 1;
+    if !new_omitted && new_lines.len() != new_count {
+        return Err(tr!("context diff hunk new lines do not match range {}", new_range));
     }
-    
-    // 合并原始和新的行到统一格式
+
     let mut hunk_lines = Vec::new();
-    let mut orig_idx = 0;
-    let mut new_idx = 0;
-    
-    while orig_idx < orig_lines.len() || new_idx < new_lines.len() {
-        if orig_idx < orig_lines.len() && new_idx < new_lines.len() {
-            let (orig_kind, orig_content) = &orig_lines[orig_idx];
-            let (new_kind, new_content) = &new_lines[new_idx];
-            
-            if *orig_kind == LineKind::Context && *new_kind == LineKind::Context {
-                // 两边都是上下文，应该相同
-                hunk_lines.push(HunkLine { kind: LineKind::Context, content: orig_content.clone() });
-                orig_idx += 1;
-                new_idx += 1;
-            } else if *orig_kind == LineKind::Remove {
-                hunk_lines.push(HunkLine { kind: LineKind::Remove, content: orig_content.clone() });
-                orig_idx += 1;
-            } else if *new_kind == LineKind::Add {
-                hunk_lines.push(HunkLine { kind: LineKind::Add, content: new_content.clone() });
-                new_idx += 1;
+    if old_omitted {
+        for (tag, content, missing_newline) in new_lines {
+            let kind = if tag == b' ' { LineKind::Context } else { LineKind::Add };
+            hunk_lines.push(HunkLine { kind, content, missing_newline });
+        }
+    } else if new_omitted {
+        for (tag, content, missing_newline) in old_lines {
+            let kind = if tag == b' ' { LineKind::Context } else { LineKind::Remove };
+            hunk_lines.push(HunkLine { kind, content, missing_newline });
+        }
+    } else {
+        // 两节都存在：上下文行一一对应，'!' 改动组先输出旧文本再输出新文本
+        let line = |kind, l: &SectionLine| HunkLine { kind, content: l.1.clone(), missing_newline: l.2 };
+        let (mut o, mut n) = (0, 0);
+        while o < old_lines.len() || n < new_lines.len() {
+            let old_tag = old_lines.get(o).map(|l| l.0);
+            let new_tag = new_lines.get(n).map(|l| l.0);
+            if old_tag == Some(b'-') {
+                hunk_lines.push(line(LineKind::Remove, &old_lines[o]));
+                o += 1;
+            } else if new_tag == Some(b'+') {
+                hunk_lines.push(line(LineKind::Add, &new_lines[n]));
+                n += 1;
+            } else if old_tag == Some(b'!') || new_tag == Some(b'!') {
+                while old_lines.get(o).is_some_and(|l| l.0 == b'!') {
+                    hunk_lines.push(line(LineKind::Remove, &old_lines[o]));
+                    o += 1;
+                }
+                while new_lines.get(n).is_some_and(|l| l.0 == b'!') {
+                    hunk_lines.push(line(LineKind::Add, &new_lines[n]));
+                    n += 1;
+                }
+            } else if old_tag.is_some() && new_tag.is_some() {
+                if old_lines[o].1 != new_lines[n].1 {
+                    return Err(tr!("context diff hunk sections have different context: {}", old_header));
+                }
+                // 上下文行写入的是新文本，换行符状态以新文本一节为准
+                hunk_lines.push(line(LineKind::Context, &new_lines[n]));
+                o += 1;
+                n += 1;
             } else {
-                // 默认情况
-                orig_idx += 1;
-                new_idx += 1;
+                return Err(tr!("context diff hunk sections have different numbers of context lines: {}", old_header));
             }
-        } else if orig_idx < orig_lines.len() {
-            let (kind, content) = &orig_lines[orig_idx];
-            hunk_lines.push(HunkLine { kind: *kind, content: content.clone() });
-            orig_idx += 1;
-        } else if new_idx < new_lines.len() {
-            let (kind, content) = &new_lines[new_idx];
-            hunk_lines.push(HunkLine { kind: *kind, content: content.clone() });
-            new_idx += 1;
         }
     }
-    
-    let consumed = i - start_idx + 1;
+
+    let orig_count = hunk_lines.iter().filter(|l| l.kind != LineKind::Add).count();
+    let new_count = hunk_lines.iter().filter(|l| l.kind != LineKind::Remove).count();
 
     Ok((PatchHunk {
         orig_start,
@@ -336,17 +790,18 @@ fn parse_context_hunk_from_vec(lines: &[String], start_idx: usize) -> Result<(Pa
         new_start,
         new_count,
         lines: hunk_lines,
-        func: None,
-    }, consumed))
+        func,
+        patch_line: start_idx + 1,
+    }, i - start_idx, old_omitted || new_omitted))
 }
 
 /// 从行向量解析normal diff的hunk块，返回(hunk, consumed_lines)
-fn parse_normal_hunk_from_vec(lines: &[String], start_idx: usize) -> Result<(PatchHunk, usize), String> {
+fn parse_normal_hunk_from_vec(lines: &[Vec<u8>], start_idx: usize) -> Result<(PatchHunk, usize), String> {
     if start_idx >= lines.len() {
         return Err("Invalid hunk start index".to_string());
     }
     
-    let first_line = &lines[start_idx];
+    let first_line = text(&lines[start_idx]);
     
     // Normal diff 格式:
     // 3c3         <- 改变第3行到第3行
@@ -369,52 +824,52 @@ fn parse_normal_hunk_from_vec(lines: &[String], start_idx: usize) -> Result<(Pat
     let mut orig_count = 0;
     let mut new_start = 0;
     let mut new_count = 0;
-    let mut op = ' ';
     
     // 解析命令行 (e.g., "3c3", "2,4d1", "1a2,3")
     if let Some(c_pos) = first_line.find('c') {
-        op = 'c';
         let (left, right) = first_line.split_at(c_pos);
         let right = &right[1..]; // skip 'c'
         
         parse_range(left, &mut orig_start, &mut orig_count);
         parse_range(right, &mut new_start, &mut new_count);
     } else if let Some(d_pos) = first_line.find('d') {
-        op = 'd';
         let (left, right) = first_line.split_at(d_pos);
         let right = &right[1..]; // skip 'd'
         
         parse_range(left, &mut orig_start, &mut orig_count);
         parse_range(right, &mut new_start, &mut new_count);
+        new_count = 0; // 'd' 的右侧是删除后所在的行，不是行数
     } else if let Some(a_pos) = first_line.find('a') {
-        op = 'a';
         let (left, right) = first_line.split_at(a_pos);
         let right = &right[1..]; // skip 'a'
         
         parse_range(left, &mut orig_start, &mut orig_count);
         parse_range(right, &mut new_start, &mut new_count);
+        orig_count = 0; // 'a' 表示在第 N 行之后插入
     }
     
     let mut hunk_lines = Vec::new();
     let mut i = start_idx + 1;
     
     // 读取原始行 (以 '<' 开头)
-    while i < lines.len() && lines[i].starts_with("< ") {
-        let content = lines[i][2..].to_string();
-        hunk_lines.push(HunkLine { kind: LineKind::Remove, content });
+    while i < lines.len() && lines[i].starts_with(b"< ") {
+        let content = lines[i][2..].to_vec();
+        hunk_lines.push(HunkLine { kind: LineKind::Remove, content, missing_newline: false });
         i += 1;
+        i += take_no_newline_marker(lines, i, &mut hunk_lines);
     }
     
     // 跳过 "---" 分隔符
-    if i < lines.len() && lines[i].starts_with("---") {
+    if i < lines.len() && lines[i].starts_with(b"---") {
         i += 1;
     }
     
     // 读取新行 (以 '>' 开头)
-    while i < lines.len() && lines[i].starts_with("> ") {
-        let content = lines[i][2..].to_string();
-        hunk_lines.push(HunkLine { kind: LineKind::Add, content });
+    while i < lines.len() && lines[i].starts_with(b"> ") {
+        let content = lines[i][2..].to_vec();
+        hunk_lines.push(HunkLine { kind: LineKind::Add, content, missing_newline: false });
         i += 1;
+        i += take_no_newline_marker(lines, i, &mut hunk_lines);
     }
     
     let consumed = i - start_idx;
@@ -426,9 +881,21 @@ fn parse_normal_hunk_from_vec(lines: &[String], start_idx: usize) -> Result<(Pat
         new_count,
         lines: hunk_lines,
         func: None,
+        patch_line: start_idx + 1,
     }, consumed))
 }
 
+/// 如果第 i 行是 "\ No newline at end of file"，标记上一行并返回 1，否则返回 0
+fn take_no_newline_marker(lines: &[Vec<u8>], i: usize, hunk_lines: &mut [HunkLine]) -> usize {
+    match (lines.get(i), hunk_lines.last_mut()) {
+        (Some(l), Some(last)) if l.starts_with(b"\\") => {
+            last.missing_newline = true;
+            1
+        }
+        _ => 0,
+    }
+}
+
 /// 解析范围字符串 (e.g., "3" -> (3, 1), "2,4" -> (2, 3))
 fn parse_range(range: &str, start: &mut usize, count: &mut usize) {
     let range = range.trim();
@@ -443,4 +910,205 @@ fn parse_range(range: &str, start: &mut usize, count: &mut usize) {
         *start = range.parse().unwrap_or(1);
         *count = 1;
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<Vec<u8>> {
+        text.lines().map(|l| l.as_bytes().to_vec()).collect()
+    }
+
+    fn kinds(hunk: &PatchHunk) -> String {
+        hunk.lines.iter().map(|l| match l.kind {
+            LineKind::Context => ' ',
+            LineKind::Add => '+',
+            LineKind::Remove => '-',
+        }).collect()
+    }
+
+    #[test]
+    fn test_unified_hunk_follows_counts() {
+        // 被删除的 "-- comment" 在补丁里是 "--- comment"，不能结束 hunk
+        let patch = lines("@@ -1,3 +1,2 @@ fn main()\n a\n--- comment\n\n@@ -10 +9 @@\n");
+        let (hunk, consumed) = parse_unified_hunk_from_vec(&patch, 0).unwrap();
+        assert_eq!(consumed, 4);
+        assert_eq!(kinds(&hunk), " - ");
+        assert_eq!(hunk.lines[1].content, b"-- comment");
+        assert_eq!(hunk.func.as_deref(), Some("fn main()"));
+    }
+
+    #[test]
+    fn test_non_utf8_content_is_kept() {
+        let patch = read_patch_lines(&b"@@ -1 +1 @@\n-caf\xe9\n+caf\xc3\xa9\n"[..]).unwrap();
+        let (hunk, _) = parse_unified_hunk_from_vec(&patch, 0).unwrap();
+        assert_eq!(hunk.lines[0].content, b"caf\xe9");
+        assert_eq!(hunk.lines[1].content, "café".as_bytes());
+    }
+
+    #[test]
+    fn test_no_newline_markers() {
+        let patch = lines("@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+a\n");
+        let (hunk, consumed) = parse_unified_hunk_from_vec(&patch, 0).unwrap();
+        assert_eq!(consumed, 4);
+        assert!(hunk.lines[0].missing_newline);
+        assert!(!hunk.lines[1].missing_newline);
+    }
+
+    #[test]
+    fn test_unified_hunk_count_mismatch() {
+        assert!(parse_unified_hunk_from_vec(&lines("@@ -1,2 +1,2 @@\n a\n"), 0).is_err());
+        assert!(parse_unified_hunk_from_vec(&lines("@@ -1,1 +1,1 @@\n-a\n-b\n+c\n"), 0).is_err());
+    }
+
+    #[test]
+    fn test_context_hunk_change_groups() {
+        let patch = lines("***************\n*** 1,4 ****\n  a\n! b\n! c\n  d\n--- 1,3 ----\n  a\n! B\n  d\n");
+        let (hunk, consumed, new_style) = parse_context_hunk_from_vec(&patch, 0).unwrap();
+        assert_eq!(consumed, 10);
+        assert!(!new_style);
+        assert_eq!(kinds(&hunk), " --+ ");
+        assert_eq!((hunk.orig_count, hunk.new_count), (4, 3));
+    }
+
+    #[test]
+    fn test_context_hunk_omitted_sections() {
+        let insert = lines("***************\n*** 4,5 ****\n--- 4,6 ----\n  x\n+ new\n  y\n");
+        let (hunk, _, new_style) = parse_context_hunk_from_vec(&insert, 0).unwrap();
+        assert!(new_style);
+        assert_eq!(kinds(&hunk), " + ");
+        assert_eq!((hunk.orig_start, hunk.orig_count), (4, 2));
+
+        let delete = lines("***************\n*** 4,6 ****\n  x\n- old\n  y\n--- 4,5 ----\n***************\n");
+        let (hunk, consumed, _) = parse_context_hunk_from_vec(&delete, 0).unwrap();
+        assert_eq!(consumed, 6);
+        assert_eq!(kinds(&hunk), " - ");
+    }
+
+    #[test]
+    fn test_patch_set_splits_files() {
+        let patch = lines(concat!(
+            "Index: a.c\n--- a/a.c\n+++ b/a.c\n@@ -1 +1 @@\n-x\n+y\n",
+            "diff -u b/b.c b/b.c\n--- b/b.c\n+++ b/b.c\n@@ -1,2 +1 @@\n--- x\n y\n",
+            "--- c.c\n+++ c.c\n@@ -1 +1 @@\n-z\n+w\n",
+        ));
+        let set = PatchSet::from_lines(&patch).unwrap();
+        assert_eq!(set.patches.len(), 3);
+        assert_eq!(set.patches[0].header.index_file.as_deref(), Some("a.c"));
+        assert_eq!(set.patches[1].header.old_file.as_deref(), Some("b/b.c"));
+        assert_eq!(kinds(&set.patches[1].hunks[0]), "- ");
+        assert_eq!(set.patches[2].header.new_file.as_deref(), Some("c.c"));
+    }
+
+    #[test]
+    fn test_git_renames_and_copies() {
+        let patch = lines(concat!(
+            "diff --git a/x.c b/y.c\nsimilarity index 100%\nrename from x.c\nrename to y.c\n",
+            "diff --git a/y.c b/x.c\nsimilarity index 90%\nrename from y.c\nrename to x.c\n",
+            "--- a/y.c\n+++ b/x.c\n@@ -1 +1 @@\n-x\n+y\n",
+            "diff --git a/my file b/my file copy\ncopy from my file\ncopy to my file copy\n",
+            "diff --git a/z b/z\nindex 1234567..89abcde 100644\n",
+        ));
+        let set = PatchSet::from_lines(&patch).unwrap();
+        assert_eq!(set.patches.len(), 3);
+        let header = &set.patches[0].header;
+        assert_eq!(header.operation, Some(GitOperation::Rename));
+        assert_eq!(header.similarity, Some(100));
+        assert_eq!((header.old_file.as_deref(), header.new_file.as_deref()), (Some("a/x.c"), Some("b/y.c")));
+        assert_eq!(set.patches[1].header.similarity, Some(90));
+        assert_eq!(set.patches[1].hunks.len(), 1);
+        assert_eq!(set.patches[2].header.operation, Some(GitOperation::Copy));
+        assert_eq!(set.patches[2].header.new_file.as_deref(), Some("b/my file copy"));
+        assert_eq!(parse_git_names("a/my file b/my file"), Some(("a/my file".into(), "b/my file".into())));
+    }
+
+    #[test]
+    fn test_git_rename_with_spaces() {
+        // git 在含空格的文件名后面加 tab，"---"/"+++" 不能覆盖 "diff --git" 中的文件名
+        let patch = lines(concat!(
+            "diff --git a/my file b/my file.txt
+similarity index 80%
+rename from my file
+rename to my file.txt
+",
+            "--- a/my file	
++++ b/my file.txt	
+@@ -1 +1 @@
+-x
++y
+",
+            "diff --git a/old name b/new name
+similarity index 90%
+rename from old name
+rename to new name
+",
+            "--- a/old name
++++ b/new name
+@@ -1 +1 @@
+-x
++y
+",
+        ));
+        let set = PatchSet::from_lines(&patch).unwrap();
+        assert_eq!(set.patches.len(), 2);
+        let names = |p: &Patch| (p.header.old_file.clone().unwrap(), p.header.new_file.clone().unwrap());
+        assert_eq!(names(&set.patches[0]), ("a/my file".into(), "b/my file.txt".into()));
+        assert_eq!(names(&set.patches[1]), ("a/old name".into(), "b/new name".into()));
+        assert_eq!(set.patches[1].hunks.len(), 1);
+    }
+
+    #[test]
+    fn test_reverse() {
+        let patch = lines(concat!(
+            "diff --git a/x.c b/x.c\nindex 1234567..89abcde 100755\n",
+            "--- a/x.c\n+++ b/x.c\n@@ -1,2 +1,3 @@\n a\n-b\n+c\n+d\n",
+            "diff --git a/gone b/gone\ndeleted file mode 100644\n--- a/gone\n+++ /dev/null\n@@ -1 +0,0 @@\n-z\n",
+        ));
+        let mut set = PatchSet::from_lines(&patch).unwrap();
+        set.reverse().unwrap();
+        let hunk = &set.patches[0].hunks[0];
+        assert_eq!((hunk.orig_start, hunk.orig_count, hunk.new_start, hunk.new_count), (1, 3, 1, 2));
+        assert_eq!(kinds(hunk), " --+");
+        assert_eq!(set.patches[0].header.sha1_old.as_deref(), Some("89abcde"));
+        assert!(set.patches[1].creates_file());
+        assert_eq!(set.patches[1].header.new_mode, Some(0o100644));
+
+        let mut ed = PatchSet::from_lines(&lines("2d\n")).unwrap();
+        assert!(ed.reverse().is_err());
+    }
+
+    #[test]
+    fn test_git_modes() {
+        let patch = lines(concat!(
+            "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n",
+            "diff --git a/empty b/empty\nnew file mode 100644\nindex 0000000..e69de29\n",
+            "diff --git a/gone b/gone\ndeleted file mode 100755\nindex e69de29..0000000\n",
+            "diff --git a/x.c b/x.c\nindex 1234567..89abcde 100755\n--- a/x.c\n+++ b/x.c\n@@ -1 +1 @@\n-x\n+y\n",
+        ));
+        let set = PatchSet::from_lines(&patch).unwrap();
+        assert_eq!(set.patches.len(), 4);
+        assert_eq!(set.patches[0].mode_change(), Some((0o100644, 0o100755)));
+        assert!(set.patches[1].creates_file());
+        assert_eq!(set.patches[1].header.new_mode, Some(0o100644));
+        assert!(set.patches[2].deletes_file());
+        assert_eq!(set.patches[2].header.sha1_old.as_deref(), Some("e69de29"));
+        let header = &set.patches[3].header;
+        assert_eq!((header.old_mode, header.new_mode), (Some(0o100755), Some(0o100755)));
+        assert_eq!(set.patches[3].mode_change(), None);
+    }
+
+    #[test]
+    fn test_strip_leading_components() {
+        assert_eq!(strip_leading_components("a/src//x.c", 0).as_deref(), Some("a/src//x.c"));
+        assert_eq!(strip_leading_components("a/src//x.c", 2).as_deref(), Some("x.c"));
+        assert_eq!(strip_leading_components("/usr/x.c", 1).as_deref(), Some("usr/x.c"));
+        assert_eq!(strip_leading_components("a/src/x.c", -1).as_deref(), Some("x.c"));
+        assert_eq!(strip_leading_components("x.c", 1), None);
+
+        // 没有 -p 时只保留文件名本身，相当于去掉了两个组件
+        let set = PatchSet::from_lines(&lines("--- a/src/x.c\n+++ b/src/x.c\n@@ -1 +1 @@\n-x\n+y\n")).unwrap();
+        assert_eq!(set.patches[0].strip_level("x.c"), Some(2));
+        assert_eq!(set.patches[0].strip_level("y.c"), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::merge::HunkResult;
+use crate::pch::{LineKind, Patch, PatchHunk};
+use crate::safe;
+
+/// 被拒绝的 hunk 写入的 .rej 文件，记录本次运行中每个 .rej 文件已经写了多少行
+///
+/// 同一个文件在一次运行中多次被修补时，后面的 hunk 追加到同一个 .rej 文件。
+#[derive(Debug, Default)]
+pub struct RejectFiles {
+    written: HashMap<String, usize>,
+}
+
+impl RejectFiles {
+    /// 把 results 中被拒绝（包括已经应用过而被跳过）的 hunk 以 unified diff 格式
+    /// 写入 target.rej，并在 results 中记下每个 hunk 在 .rej 文件中的行号
+    ///
+    /// 没有被拒绝的 hunk 时返回 None；dry_run 为 true 时只计算行号，不写文件。
+    pub fn save(
+        &mut self,
+        target: &str,
+        patch: &Patch,
+        results: &mut [HunkResult],
+        dry_run: bool,
+    ) -> io::Result<Option<String>> {
+        if !results.iter().any(HunkResult::is_rejected) {
+            return Ok(None);
+        }
+        let rej_path = format!("{}.rej", target);
+        let append = self.written.contains_key(&rej_path);
+        let mut lines: Vec<Vec<u8>> = Vec::new();
+        if !append {
+            let old = patch.header.old_file.as_deref().unwrap_or(target);
+            let new = patch.header.new_file.as_deref().unwrap_or(target);
+            lines.push(format!("--- {}", old).into_bytes());
+            lines.push(format!("+++ {}", new).into_bytes());
+        }
+
+        let base = self.written.get(&rej_path).copied().unwrap_or(0);
+        for (hunk, result) in patch.hunks.iter().zip(results.iter_mut()) {
+            if result.is_rejected() {
+                result.reject_line = Some(base + lines.len() + 1);
+                lines.extend(format_hunk(hunk));
+            }
+        }
+
+        if !dry_run {
+            let mut file = if append { safe::safe_append(&rej_path)? } else { safe::safe_open(&rej_path, true)? };
+            for line in &lines {
+                file.write_all(line)?;
+                file.write_all(b"\n")?;
+            }
+        }
+        *self.written.entry(rej_path.clone()).or_default() += lines.len();
+        Ok(Some(rej_path))
+    }
+}
+
+/// 把 hunk 格式化为 unified diff 的一个 hunk（不含行尾换行符）
+fn format_hunk(hunk: &PatchHunk) -> Vec<Vec<u8>> {
+    let func = hunk.func.as_deref().map(|f| format!(" {}", f)).unwrap_or_default();
+    let mut lines = vec![
+        format!(
+            "@@ -{},{} +{},{} @@{}",
+            hunk.orig_start, hunk.orig_count, hunk.new_start, hunk.new_count, func
+        )
+        .into_bytes(),
+    ];
+    for line in &hunk.lines {
+        let tag = match line.kind {
+            LineKind::Context => b' ',
+            LineKind::Add => b'+',
+            LineKind::Remove => b'-',
+        };
+        let mut text = vec![tag];
+        text.extend_from_slice(&line.content);
+        lines.push(text);
+        if line.missing_newline {
+            lines.push(b"\\ No newline at end of file".to_vec());
+        }
+    }
+    lines
+}
\ No newline at end of file
@@ -0,0 +1,318 @@
+use std::sync::atomic::Ordering;
+
+use crate::common;
+use crate::i18n::tr;
+use crate::merge::{HunkResult, MergeResult};
+use crate::pch::{GitOperation, LineKind, Patch};
+
+/// 一个文件的补丁统计和应用结果，用于 --stat、--numstat、--summary 和 --format=json
+#[derive(Debug, Clone)]
+pub struct FileStat {
+    pub path: String,
+    /// 从补丁中的文件名得到 path 时去掉的路径组件数
+    pub strip: Option<usize>,
+    /// 补丁新增的行数
+    pub added: usize,
+    /// 补丁删除的行数
+    pub removed: usize,
+    /// 每个 hunk 的试应用结果
+    pub hunks: Vec<HunkResult>,
+    pub created: bool,
+    pub deleted: bool,
+    pub old_mode: Option<u32>,
+    pub new_mode: Option<u32>,
+    /// 重命名或复制时的源文件和操作
+    pub source: Option<String>,
+    pub operation: Option<GitOperation>,
+    /// git 补丁中的相似度百分比
+    pub similarity: Option<u32>,
+    /// 结果与补丁 index 行中新文件的 hash 一致，没有 hash 时为 None
+    pub exact: Option<bool>,
+    /// 备份文件名，没有备份时为 None
+    pub backup: Option<String>,
+    /// 保存被拒绝的 hunk 的 .rej 文件
+    pub reject_file: Option<String>,
+    /// 无法处理该文件时的错误信息
+    pub error: Option<String>,
+}
+
+impl FileStat {
+    pub fn new(path: &str, patch: &Patch, hunks: &[HunkResult]) -> Self {
+        let count = |kind| patch.hunks.iter().flat_map(|h| &h.lines).filter(|l| l.kind == kind).count();
+        FileStat {
+            path: path.to_string(),
+            strip: patch.strip_level(path),
+            added: count(LineKind::Add),
+            removed: count(LineKind::Remove),
+            hunks: hunks.to_vec(),
+            created: patch.creates_file(),
+            deleted: patch.header.new_file.as_deref() == Some("/dev/null"),
+            old_mode: patch.header.old_mode,
+            new_mode: patch.header.new_mode,
+            source: patch.source_file(),
+            operation: patch.header.operation,
+            similarity: patch.header.similarity,
+            exact: None,
+            backup: None,
+            reject_file: None,
+            error: None,
+        }
+    }
+
+    /// 无法处理的文件，path 为目标文件名（找不到时为补丁中的文件名）
+    pub fn error(path: &str, patch: &Patch, error: String) -> Self {
+        FileStat { hunks: Vec::new(), error: Some(error), ..FileStat::new(path, patch, &[]) }
+    }
+
+    /// 显示用的文件名，重命名或复制时与 git 一样为 "源文件 => 目标文件"
+    fn display_name(&self) -> String {
+        match &self.source {
+            Some(source) => format!("{} => {}", source, self.path),
+            None => self.path.clone(),
+        }
+    }
+
+    /// 文件的处理结果：error、failed 或 applied
+    fn status(&self) -> &'static str {
+        if self.error.is_some() {
+            "error"
+        } else if self.failed() > 0 {
+            "failed"
+        } else {
+            "applied"
+        }
+    }
+
+    /// 成功应用的 hunk 数
+    fn applied(&self) -> usize {
+        self.hunks.iter().filter(|h| h.result == MergeResult::Clean).count()
+    }
+
+    /// 应用失败的 hunk 数
+    fn failed(&self) -> usize {
+        self.hunks.iter().filter(|h| h.is_failure()).count()
+    }
+
+    /// 各 hunk 中最大的偏移行数（绝对值）
+    fn max_offset(&self) -> usize {
+        self.hunks.iter().map(|h| h.offset.unsigned_abs()).max().unwrap_or(0)
+    }
+
+    /// 各 hunk 中最大的模糊匹配行数
+    fn max_fuzz(&self) -> usize {
+        self.hunks.iter().map(|h| h.fuzz).max().unwrap_or(0)
+    }
+}
+
+/// diffstat 图形部分的最大宽度
+const STAT_WIDTH: usize = 80;
+
+/// --stat：与 git apply --stat 相同的 diffstat，最后附上汇总行
+///
+/// 有 hunk 需要偏移、模糊匹配或者应用失败的文件，在汇总之后逐个列出。
+pub fn print_stat(stats: &[FileStat]) {
+    let name_width = stats.iter().map(|s| s.display_name().chars().count()).max().unwrap_or(0);
+    let max_change = stats.iter().map(|s| s.added + s.removed).max().unwrap_or(0);
+    let count_width = max_change.to_string().len();
+    let graph_width = STAT_WIDTH.saturating_sub(name_width + count_width + 4).max(10);
+
+    for stat in stats {
+        let (mut plus, mut minus) = (stat.added, stat.removed);
+        if max_change > graph_width {
+            // 按比例缩小，但有改动的一侧至少画一个字符
+            let scale = |n: usize| if n == 0 { 0 } else { (n * graph_width).div_ceil(max_change) };
+            (plus, minus) = (scale(plus), scale(minus));
+            if plus + minus > graph_width {
+                plus = graph_width - minus;
+            }
+        }
+        let line = format!(
+            " {:<name_width$} | {:>count_width$} {}{}",
+            stat.display_name(),
+            stat.added + stat.removed,
+            "+".repeat(plus),
+            "-".repeat(minus),
+        );
+        // 没有改动的文件（如单纯的重命名）后面不留空格
+        println!("{}", line.trim_end());
+    }
+
+    let added: usize = stats.iter().map(|s| s.added).sum();
+    let removed: usize = stats.iter().map(|s| s.removed).sum();
+    let plural = |n: usize, one: &'static str, many: &'static str| tr!(if n == 1 { one } else { many }, n);
+    let totals = [
+        plural(stats.len(), "{} file changed", "{} files changed"),
+        plural(added, "{} insertion(+)", "{} insertions(+)"),
+        plural(removed, "{} deletion(-)", "{} deletions(-)"),
+    ];
+    println!(" {}", totals.join(tr!(", ")));
+
+    for stat in stats {
+        let (offset, fuzz, failed) = (stat.max_offset(), stat.max_fuzz(), stat.failed());
+        if offset == 0 && fuzz == 0 && failed == 0 {
+            continue;
+        }
+        println!(
+            " {}",
+            tr!(
+                "{}: {}/{} hunks apply, max offset {}, max fuzz {}",
+                stat.path,
+                stat.applied(),
+                stat.hunks.len(),
+                offset,
+                fuzz,
+            )
+        );
+    }
+}
+
+/// --numstat：每个文件一行，以制表符分隔
+///
+/// 各列依次为：新增行数、删除行数、可以应用的 hunk 数、hunk 总数、
+/// 最大偏移行数、最大模糊匹配行数、文件名。
+pub fn print_numstat(stats: &[FileStat]) {
+    for stat in stats {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            stat.added,
+            stat.removed,
+            stat.applied(),
+            stat.hunks.len(),
+            stat.max_offset(),
+            stat.max_fuzz(),
+            stat.display_name(),
+        );
+    }
+}
+
+/// --summary：与 git apply --summary 相同，列出新建、删除、重命名、复制和权限变化的文件
+pub fn print_summary(stats: &[FileStat]) {
+    let mode = |m: Option<u32>| m.map(|m| format!(" mode {:06o}", m)).unwrap_or_default();
+    for stat in stats {
+        if let (Some(source), Some(operation)) = (&stat.source, stat.operation) {
+            let similarity = stat.similarity.map(|s| format!(" ({}%)", s)).unwrap_or_default();
+            println!(" {} {} => {}{}", operation_name(operation), source, stat.path, similarity);
+        }
+        if stat.created {
+            println!(" create{} {}", mode(stat.new_mode), stat.path);
+        } else if stat.deleted {
+            println!(" delete{} {}", mode(stat.old_mode), stat.path);
+        } else if let (Some(old), Some(new)) = (stat.old_mode, stat.new_mode)
+            && old != new
+        {
+            println!(" mode change {:06o} => {:06o} {}", old, new, stat.path);
+        }
+    }
+}
+
+/// --format=json：以 JSON 输出每个文件和每个 hunk 的应用结果
+pub fn print_json(stats: &[FileStat]) {
+    let dry_run = common::DRY_RUN.load(Ordering::Relaxed);
+    let opt_str = |s: &Option<String>| s.as_deref().map_or("null".to_string(), json_string);
+    let opt_num = |n: Option<usize>| n.map_or("null".to_string(), |n| n.to_string());
+
+    println!("{{");
+    println!("  \"dry_run\": {},", dry_run);
+    println!("  \"files\": [");
+    for (i, stat) in stats.iter().enumerate() {
+        println!("    {{");
+        println!("      \"path\": {},", json_string(&stat.path));
+        println!("      \"source\": {},", opt_str(&stat.source));
+        println!(
+            "      \"operation\": {},",
+            stat.operation.map_or("null".to_string(), |op| json_string(operation_name(op)))
+        );
+        println!("      \"strip\": {},", opt_num(stat.strip));
+        println!("      \"status\": \"{}\",", stat.status());
+        println!("      \"error\": {},", opt_str(&stat.error));
+        println!("      \"backup\": {},", opt_str(&stat.backup));
+        println!("      \"reject_file\": {},", opt_str(&stat.reject_file));
+        println!("      \"added\": {},", stat.added);
+        println!("      \"removed\": {},", stat.removed);
+        println!("      \"exact\": {},", stat.exact.map_or("null".to_string(), |e| e.to_string()));
+        println!("      \"hunks\": [");
+        for (n, hunk) in stat.hunks.iter().enumerate() {
+            let reject = match (&stat.reject_file, hunk.reject_line) {
+                (Some(file), Some(line)) => format!("{{\"file\": {}, \"line\": {}}}", json_string(file), line),
+                _ => "null".to_string(),
+            };
+            println!(
+                "        {{\"index\": {}, \"nominal\": {}, \"actual\": {}, \"offset\": {}, \"fuzz\": {}, \"whitespace\": {}, \"result\": \"{:?}\", \"reject\": {}}}{}",
+                n + 1,
+                hunk.nominal,
+                opt_num(hunk.applied_at),
+                hunk.offset,
+                hunk.fuzz,
+                hunk.whitespace,
+                hunk.result,
+                reject,
+                if n + 1 < stat.hunks.len() { "," } else { "" },
+            );
+        }
+        println!("      ]");
+        println!("    }}{}", if i + 1 < stats.len() { "," } else { "" });
+    }
+    println!("  ]");
+    println!("}}");
+}
+
+/// git 扩展头中文件操作的名称，与 git apply --summary 相同
+fn operation_name(operation: GitOperation) -> &'static str {
+    match operation {
+        GitOperation::Rename => "rename",
+        GitOperation::Copy => "copy",
+    }
+}
+
+/// 转换为 JSON 字符串字面量
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge;
+    use crate::pch::PatchSet;
+
+    /// 三个 hunk 共增加 3 行、删除 1 行的补丁，各个 hunk 按 offsets 中的偏移量应用
+    fn stat(offsets: &[isize]) -> FileStat {
+        let text = "--- a/a.c\n+++ b/a.c\n@@ -1 +1,2 @@\n-x\n+y\n+z\n@@ -10 +11,2 @@\n a\n+b\n@@ -20 +22 @@\n c\n";
+        let lines: Vec<Vec<u8>> = text.lines().map(|l| l.as_bytes().to_vec()).collect();
+        let patch = &PatchSet::from_lines(&lines).unwrap().patches[0];
+        let hunks: Vec<HunkResult> = merge::reject_all(patch).into_iter().zip(offsets)
+            .map(|(r, &offset)| HunkResult { result: MergeResult::Clean, applied_at: Some(r.nominal), offset, ..r })
+            .collect();
+        FileStat::new("a.c", patch, &hunks)
+    }
+
+    #[test]
+    fn test_stat_counters() {
+        let s = stat(&[0, -4, 2]);
+        assert_eq!((s.added, s.removed, s.strip), (3, 1, Some(1)));
+        assert_eq!(s.applied(), 3);
+        assert_eq!(s.failed(), 0);
+        assert_eq!(s.max_offset(), 4);
+        assert_eq!(s.max_fuzz(), 0);
+        assert_eq!(s.status(), "applied");
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+}
\ No newline at end of file
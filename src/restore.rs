@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use crate::common;
+use crate::i18n::tr;
+use crate::pch::{GitOperation, Patch, PatchSet};
+use crate::safe;
+use crate::util;
+
+/// --restore：按当前的备份设置，把补丁涉及的文件恢复成备份中的内容
+///
+/// 被补丁新建的文件直接删除，其余文件（包括被补丁删除的文件）从备份恢复；
+/// 备份文件在恢复时被改名回原文件。重命名和复制的目标文件原来不存在时
+/// 与新建的文件一样删除，重命名的源文件从备份恢复。返回无法恢复的文件数。
+pub fn restore_patch_set(patch_set: &PatchSet, orig_file: Option<&str>) -> usize {
+    let mut failures = 0;
+    let mut seen = HashSet::new();
+
+    for patch in &patch_set.patches {
+        if orig_file.is_none()
+            && let Some(operation) = patch.header.operation
+        {
+            // 目标文件，以及重命名时被删除的源文件
+            let source = (operation == GitOperation::Rename).then(|| (patch.source_file(), true));
+            for (name, source) in [Some((patch.target_file(), false)), source].into_iter().flatten() {
+                let Some(name) = name else {
+                    eprintln!("{}", tr!("can't restore: no file name in patch ({})", patch.file_names()));
+                    failures += 1;
+                    continue;
+                };
+                if !seen.insert(name.clone()) {
+                    continue;
+                }
+                let restore = if source { restore_backup } else { restore_or_remove };
+                if let Err(msg) = check_safe(&name).and_then(|_| restore(&name)) {
+                    eprintln!("{}", tr!("can't restore {}: {}", name, msg));
+                    failures += 1;
+                }
+            }
+            continue;
+        }
+
+        let candidates = match orig_file {
+            Some(name) => vec![name.to_string()],
+            None => patch.candidate_files(),
+        };
+        let Some(target) = candidates.first() else {
+            eprintln!("{}", tr!("can't restore: no file name in patch ({})", patch.file_names()));
+            failures += 1;
+            continue;
+        };
+        if !seen.insert(target.clone()) {
+            continue;
+        }
+
+        if let Err(msg) = restore_file(patch, &candidates) {
+            eprintln!("{}", tr!("can't restore {}: {}", target, msg));
+            failures += 1;
+        }
+    }
+
+    failures
+}
+
+/// 补丁中的文件名不能指向工作目录以外
+fn check_safe(name: &str) -> Result<(), String> {
+    if safe::is_allowed(Path::new(name)) {
+        Ok(())
+    } else {
+        Err(tr!("refusing to patch unsafe path: {}", name))
+    }
+}
+
+/// 恢复一个文件，candidates 为补丁中的候选文件名
+///
+/// --dry-run 时只报告要做的事，不修改任何文件。
+fn restore_file(patch: &Patch, candidates: &[String]) -> Result<(), String> {
+    candidates.iter().try_for_each(|name| check_safe(name))?;
+    if patch.creates_file() {
+        return remove_created(&candidates[0], util::find_backup(&candidates[0]));
+    }
+    let Some((target, backup)) = candidates
+        .iter()
+        .find_map(|name| util::find_backup(name).map(|backup| (name, backup)))
+    else {
+        return Err(tr!("no backup file found").to_string());
+    };
+    move_back(&backup, target)
+}
+
+/// 重命名或复制的目标文件：原来存在时（如两个文件互换）从备份恢复，否则删除
+fn restore_or_remove(target: &str) -> Result<(), String> {
+    match util::find_backup(target) {
+        Some(backup) if !is_placeholder(&backup) => move_back(&backup, target),
+        backup => remove_created(target, backup),
+    }
+}
+
+/// 从备份恢复 target
+fn restore_backup(target: &str) -> Result<(), String> {
+    let backup = util::find_backup(target).ok_or_else(|| tr!("no backup file found").to_string())?;
+    move_back(&backup, target)
+}
+
+/// 新建文件时留下的空备份只是占位
+fn is_placeholder(backup: &str) -> bool {
+    fs::metadata(backup).is_ok_and(|m| m.len() == 0)
+}
+
+/// 删除补丁新建的 target 以及它的占位备份
+fn remove_created(target: &str, backup: Option<String>) -> Result<(), String> {
+    if common::DRY_RUN.load(Ordering::Relaxed) {
+        util::say(&tr!("would remove created file {}", target));
+        return Ok(());
+    }
+    if fs::symlink_metadata(target).is_ok() {
+        safe::safe_unlink(target).map_err(|e| e.to_string())?;
+        util::remove_empty_dirs(target);
+    }
+    if let Some(backup) = backup.filter(|b| is_placeholder(b)) {
+        let _ = safe::safe_unlink(&backup);
+    }
+    util::say(&tr!("removed created file {}", target));
+    Ok(())
+}
+
+/// 把备份文件改名回 target，无法改名时复制
+fn move_back(backup: &str, target: &str) -> Result<(), String> {
+    if common::DRY_RUN.load(Ordering::Relaxed) {
+        util::say(&tr!("would restore {1} from {0}", backup, target));
+        return Ok(());
+    }
+    util::make_parent_dirs(target).map_err(|e| e.to_string())?;
+    if safe::safe_rename(backup, target).is_err() {
+        safe::safe_copy(backup, target).map_err(|e| e.to_string())?;
+        let _ = safe::safe_unlink(backup);
+    }
+    util::say(&tr!("restored {1} from {0}", backup, target));
+    Ok(())
+}
\ No newline at end of file
@@ -1,12 +1,22 @@
-use std::fs::{self, File, OpenOptions, Metadata};
-use std::io;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
 use std::os::unix::fs::OpenOptionsExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::i18n::tr;
 
 /// 是否允许不安全路径（如访问工作目录以外的文件）
 pub static mut UNSAFE: bool = false;
 
-/// 检查路径是否安全（不包含绝对路径或 ..）
+/// 命令行上直接指定的要修补的文件所在的目录。用户自己给出的文件不受安全路径的限制，
+/// 它旁边的临时文件和备份文件也一样
+pub static TRUSTED_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// -B 指定的备份前缀所在的目录。备份位置由用户给出，其下的备份文件也不受安全路径的限制
+pub static BACKUP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// 检查路径是否安全（不包含绝对路径或 ..，也不经过符号链接的目录）
 pub fn is_safe_path(path: &Path) -> bool {
     if path.is_absolute() {
         return false;
@@ -17,23 +27,93 @@ pub fn is_safe_path(path: &Path) -> bool {
             return false;
         }
     }
-    true
+    !has_symlinked_dir(path)
 }
 
-/// 安全地打开文件
-pub fn safe_open<P: AsRef<Path>>(path: P, write: bool) -> io::Result<File> {
-    let path = path.as_ref();
-    unsafe {
-        if !UNSAFE && !is_safe_path(path) {
-            return Err(io::Error::new(
-                io::ErrorKind::PermissionDenied,
-                format!("不允许访问不安全路径: {:?}", path)
-            ));
+/// path 中已经存在的上级目录里是否有符号链接
+///
+/// O_NOFOLLOW 只对最后一个部分起作用，中途的符号链接仍会被跟随，
+/// 可能把路径带到工作目录以外，所以要在文件系统上逐级检查。
+fn has_symlinked_dir(path: &Path) -> bool {
+    let mut dir = PathBuf::new();
+    path.parent().is_some_and(|parent| {
+        parent.components().any(|comp| {
+            dir.push(comp);
+            is_symlink(&dir)
+        })
+    })
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+/// 是否允许访问 path：安全路径、命令行上直接指定的文件（及其旁边的文件）、
+/// -B 指定的目录下的备份文件，或者允许不安全路径时的任意路径
+pub fn is_allowed(path: &Path) -> bool {
+    use std::path::Component::Normal;
+    let unsafe_paths = unsafe { UNSAFE };
+    unsafe_paths
+        || is_safe_path(path)
+        || TRUSTED_DIR.get().is_some_and(|dir| path.parent() == Some(dir.as_path()))
+        || BACKUP_DIR.get().is_some_and(|dir| {
+            path.strip_prefix(dir).is_ok_and(|rest| rest.components().all(|c| matches!(c, Normal(_))))
+        })
+}
+
+/// 检查符号链接 link 指向的 target 是否仍在工作目录以内
+///
+/// 相对路径的 target 从 link 所在的目录算起，中途的 ".." 不能越过工作目录，
+/// 也不能经过已经存在的符号链接（否则 ".." 不一定回到上一级目录）。
+pub fn is_safe_link(link: &Path, target: &Path) -> bool {
+    use std::path::Component::*;
+    if !is_safe_path(link) {
+        return false;
+    }
+    let mut dir = link.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut depth = dir.components().filter(|c| matches!(c, Normal(_))).count();
+    let mut comps = target.components().peekable();
+    while let Some(comp) = comps.next() {
+        match comp {
+            Normal(name) => {
+                depth += 1;
+                dir.push(name);
+                if comps.peek().is_some() && is_symlink(&dir) {
+                    return false;
+                }
+            }
+            CurDir => {}
+            ParentDir if depth > 0 => {
+                depth -= 1;
+                dir.pop();
+            }
+            ParentDir | RootDir | Prefix(_) => return false,
         }
     }
+    true
+}
 
+/// 安全地打开文件，write 为 true 时创建或清空文件用于写入
+pub fn safe_open<P: AsRef<Path>>(path: P, write: bool) -> io::Result<File> {
     let mut opts = OpenOptions::new();
-    opts.read(!write).write(write);
+    opts.read(!write).write(write).create(write).truncate(write);
+    open_with(path.as_ref(), opts)
+}
+
+/// 安全地打开文件用于追加，文件不存在时创建
+pub fn safe_append<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let mut opts = OpenOptions::new();
+    opts.append(true).create(true);
+    open_with(path.as_ref(), opts)
+}
+
+fn open_with(path: &Path, mut opts: OpenOptions) -> io::Result<File> {
+    if !is_allowed(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            tr!("refusing to access unsafe path: {}", path.display())
+        ));
+    }
 
     // unix: O_NOFOLLOW 可防止符号链接攻击
     #[cfg(unix)]
@@ -44,30 +124,67 @@ pub fn safe_open<P: AsRef<Path>>(path: P, write: bool) -> io::Result<File> {
     opts.open(path)
 }
 
+/// 安全读取整个文件，不跟随符号链接
+pub fn safe_read<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    safe_open(path, false)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// 安全写入整个文件
+pub fn safe_write<P: AsRef<Path>>(path: P, data: &[u8]) -> io::Result<()> {
+    safe_open(path, true)?.write_all(data)
+}
+
+/// 安全地复制文件，两端都不跟随符号链接，保留原文件的权限
+pub fn safe_copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let mut source = safe_open(from, false)?;
+    let permissions = source.metadata()?.permissions();
+    let mut dest = safe_open(to, true)?;
+    io::copy(&mut source, &mut dest)?;
+    dest.set_permissions(permissions)
+}
+
 /// 安全读取符号链接内容
 pub fn safe_readlink<P: AsRef<Path>>(path: P) -> io::Result<String> {
     let path = path.as_ref();
+    if !is_allowed(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            tr!("refusing to access unsafe path: {}", path.display())
+        ));
+    }
+    fs::read_link(path).map(|p| p.to_string_lossy().into_owned())
+}
+
+/// 检查能否创建指向 target 的符号链接 link（见 safe_symlink）
+pub fn check_symlink<P: AsRef<Path>, Q: AsRef<Path>>(target: P, link: Q) -> io::Result<()> {
+    let (target, link) = (target.as_ref(), link.as_ref());
     unsafe {
-        if !UNSAFE && !is_safe_path(path) {
+        if !UNSAFE && !is_safe_link(link, target) {
             return Err(io::Error::new(
                 io::ErrorKind::PermissionDenied,
-                format!("不允许访问不安全路径: {:?}", path)
+                tr!("refusing to create symbolic link {} pointing to unsafe path: {}", link.display(), target.display())
             ));
         }
     }
-    fs::read_link(path).map(|p| p.to_string_lossy().into_owned())
+    Ok(())
+}
+
+/// 安全创建符号链接：链接本身以及它指向的位置都不能在工作目录以外
+pub fn safe_symlink<P: AsRef<Path>, Q: AsRef<Path>>(target: P, link: Q) -> io::Result<()> {
+    check_symlink(&target, &link)?;
+    std::os::unix::fs::symlink(target, link)
 }
 
 /// 安全删除文件
 pub fn safe_unlink<P: AsRef<Path>>(path: P) -> io::Result<()> {
     let path = path.as_ref();
-    unsafe {
-        if !UNSAFE && !is_safe_path(path) {
-            return Err(io::Error::new(
-                io::ErrorKind::PermissionDenied,
-                format!("不允许删除不安全路径: {:?}", path)
-            ));
-        }
+    if !is_allowed(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            tr!("refusing to remove unsafe path: {}", path.display())
+        ));
     }
     if path.is_dir() {
         fs::remove_dir(path)
@@ -76,32 +193,28 @@ pub fn safe_unlink<P: AsRef<Path>>(path: P) -> io::Result<()> {
     }
 }
 
-/// 安全创建目录
-pub fn safe_mkdir<P: AsRef<Path>>(path: P) -> io::Result<()> {
-    let path = path.as_ref();
-    unsafe {
-        if !UNSAFE && !is_safe_path(path) {
-            return Err(io::Error::new(
-                io::ErrorKind::PermissionDenied,
-                format!("不允许创建不安全路径: {:?}", path)
-            ));
-        }
+/// 安全地改名，原文件名和新文件名都必须是允许访问的路径
+pub fn safe_rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if let Some(path) = [from, to].into_iter().find(|path| !is_allowed(path)) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            tr!("refusing to access unsafe path: {}", path.display())
+        ));
     }
-    fs::create_dir_all(path)
+    fs::rename(from, to)
 }
 
-/// 安全获取文件状态
-pub fn safe_stat<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
+/// 安全创建目录
+pub fn safe_mkdir<P: AsRef<Path>>(path: P) -> io::Result<()> {
     let path = path.as_ref();
-    unsafe {
-        if !UNSAFE && !is_safe_path(path) {
-            return Err(io::Error::new(
-                io::ErrorKind::PermissionDenied,
-                format!("不允许stat不安全路径: {:?}", path)
-            ));
-        }
+    if !is_allowed(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            tr!("refusing to create unsafe path: {}", path.display())
+        ));
     }
-    fs::metadata(path)
+    fs::create_dir_all(path)
 }
 
 #[cfg(test)]
@@ -113,4 +226,40 @@ mod tests {
         assert!(!is_safe_path(Path::new("/etc/passwd")));
         assert!(!is_safe_path(Path::new("../etc/passwd")));
     }
+
+    #[test]
+    fn test_refuse_unsafe_paths() {
+        // 补丁头中的 ../outside 不能被读取、改写、改名或删除
+        let denied = |r: io::Result<()>| r.is_err_and(|e| e.kind() == io::ErrorKind::PermissionDenied);
+        assert!(!is_allowed(Path::new("../outside")));
+        assert!(denied(safe_read("../outside").map(drop)));
+        assert!(denied(safe_write("../outside", b"x")));
+        assert!(denied(safe_rename("file", "../outside")));
+        assert!(denied(safe_unlink("../outside")));
+        assert!(denied(safe_mkdir("../outside/dir")));
+        assert!(denied(safe_append("../outside").map(drop)));
+        assert!(denied(safe_copy("Cargo.toml", "../outside")));
+    }
+
+    #[test]
+    fn test_safe_link() {
+        assert!(is_safe_link(Path::new("a/b/link"), Path::new("../c/./file")));
+        assert!(is_safe_link(Path::new("link"), Path::new("dir/file")));
+        assert!(!is_safe_link(Path::new("a/link"), Path::new("../../file")));
+        assert!(!is_safe_link(Path::new("link"), Path::new("/etc/passwd")));
+        assert!(!is_safe_link(Path::new("../link"), Path::new("file")));
+    }
+
+    #[test]
+    fn test_symlinked_dirs() {
+        // 先建 x -> .，再经过它建 x/x/y -> ../../secret，实际指向工作目录以外
+        let dir = fs::canonicalize(std::env::temp_dir()).unwrap().join(format!("patch-safe-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("real")).unwrap();
+        std::os::unix::fs::symlink(".", dir.join("x")).unwrap();
+        assert!(has_symlinked_dir(&dir.join("x/x/y")));
+        assert!(has_symlinked_dir(&dir.join("x/y")));
+        assert!(!has_symlinked_dir(&dir.join("x")));
+        assert!(!has_symlinked_dir(&dir.join("real/y")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
\ No newline at end of file
@@ -0,0 +1,41 @@
+use ::sha1::{Digest, Sha1};
+
+/// git 的 blob id：对 "blob <长度>\0<内容>" 计算 SHA-1，以小写十六进制表示
+pub fn git_blob_id(data: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", data.len()));
+    hasher.update(data);
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// index 行中可以用来核对的 hash：全 0（文件不存在）或者太短、不是十六进制的不算
+///
+/// 缩写至少要有 git 默认的 7 位，才能认为它确定地对应一个文件。
+pub fn usable(hash: Option<&str>) -> Option<&str> {
+    hash.filter(|h| h.len() >= 7 && h.len() <= 40 && h.bytes().all(|b| b.is_ascii_hexdigit()))
+        .filter(|h| h.bytes().any(|b| b != b'0'))
+}
+
+/// id 是否与 index 行中的 hash 相符，hash 可以是缩写
+pub fn matches(id: &str, hash: &str) -> bool {
+    !hash.is_empty() && id.starts_with(&hash.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_git_blob_id() {
+        assert_eq!(git_blob_id(b""), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        assert_eq!(git_blob_id(b"hello\n"), "ce013625030ba8dba906f756967f9e9ca394464a");
+        let data: Vec<u8> = (0..4).flat_map(|_| 0..=255u8).collect();
+        assert_eq!(git_blob_id(&data), "c8b49c8cd518e58491924bfc364ff26e01a85009");
+        assert!(matches(&git_blob_id(b""), "E69DE29"));
+        assert!(!matches(&git_blob_id(b""), ""));
+        assert_eq!(usable(Some("e69de29")), Some("e69de29"));
+        assert_eq!(usable(Some("0000000")), None);
+        assert_eq!(usable(Some("e69d")), None);
+        assert_eq!(usable(None), None);
+    }
+}
\ No newline at end of file
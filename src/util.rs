@@ -1,8 +1,220 @@
-pub fn fatal(msg: &str) -> ! {
-    eprintln!("fatal: {}", msg);
-    std::process::exit(2);
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::backupfile::{self, BackupNaming, BackupType};
+use crate::common::{self, Verbosity};
+use crate::i18n::tr;
+use crate::safe;
+
+/// 当前的输出详细程度
+pub fn verbosity() -> Verbosity {
+    match common::VERBOSITY.load(Ordering::Relaxed) {
+        v if v == Verbosity::Silent as i32 => Verbosity::Silent,
+        v if v == Verbosity::Verbose as i32 => Verbosity::Verbose,
+        _ => Verbosity::Default,
+    }
 }
 
+/// 输出普通信息，-s 时不输出
 pub fn say(msg: &str) {
-    println!("{}", msg);
+    if verbosity() != Verbosity::Silent {
+        println!("{}", msg);
+    }
+}
+
+/// 输出只在 --verbose 时显示的信息
+pub fn verbose(msg: &str) {
+    if verbosity() == Verbosity::Verbose {
+        println!("{}", msg);
+    }
+}
+
+/// 是否打开了调试跟踪
+pub fn debugging() -> bool {
+    common::DEBUG.load(Ordering::Relaxed)
+}
+
+/// 调试跟踪：设置了 PATCH_DEBUG 时输出到标准错误，msg 只在需要时才生成
+///
+/// 跟踪信息不翻译，便于不同语言环境下比较。
+pub fn debug(msg: impl FnOnce() -> String) {
+    if debugging() {
+        eprintln!("patch: debug: {}", msg());
+    }
+}
+
+/// 去掉行尾的 '\r'（CRLF 行在按 '\n' 切分后留下的部分）
+pub fn strip_cr(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// 比较两行内容；除非指定了 --binary，否则忽略行尾的 '\r'
+pub fn lines_match(a: &[u8], b: &[u8]) -> bool {
+    if crate::common::BINARY.load(std::sync::atomic::Ordering::Relaxed) {
+        a == b
+    } else {
+        strip_cr(a) == strip_cr(b)
+    }
+}
+
+/// 比较两行内容，忽略空白的差异：连续的空白视为一个，行首行尾的空白不计
+pub fn lines_match_loose(a: &[u8], b: &[u8]) -> bool {
+    let words_a = a.split(u8::is_ascii_whitespace).filter(|w| !w.is_empty());
+    let words_b = b.split(u8::is_ascii_whitespace).filter(|w| !w.is_empty());
+    words_a.eq(words_b)
+}
+
+/// 与 target 位于同一目录的临时文件名，写完后用改名替换 target
+pub fn temp_name(target: &str) -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let (dir, base) = match target.rfind('/') {
+        Some(pos) => target.split_at(pos + 1),
+        None => ("", target),
+    };
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{}.{}.patch{}-{}", dir, base, std::process::id(), n)
+}
+
+/// 给 from 建一个硬链接 to，文件系统不支持硬链接时复制一份
+pub fn link_or_copy(from: &str, to: &str) -> io::Result<()> {
+    if fs::hard_link(from, to).is_err() {
+        safe::safe_copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// 当前的备份方式：-V 优先，其次是 PATCH_VERSION_CONTROL 和 VERSION_CONTROL 环境变量
+pub fn backup_type() -> BackupType {
+    if let Some(t) = common::BACKUP_TYPE.get() {
+        return *t;
+    }
+    ["PATCH_VERSION_CONTROL", "VERSION_CONTROL"]
+        .iter()
+        .find_map(|var| env::var(var).ok())
+        .and_then(|name| BackupType::from_name(&name))
+        .unwrap_or(BackupType::Existing)
+}
+
+/// 判断是否需要备份原文件；mismatch 表示有 hunk 没有原样应用
+pub fn wants_backup(mismatch: bool) -> bool {
+    let wanted = common::BACKUP.load(Ordering::Relaxed)
+        || (mismatch && common::BACKUP_IF_MISMATCH.load(Ordering::Relaxed));
+    wanted && backup_type() != BackupType::None
+}
+
+/// 由 -B/-Y/-z 和 SIMPLE_BACKUP_SUFFIX 环境变量决定的备份文件命名方式
+fn backup_naming() -> BackupNaming {
+    BackupNaming {
+        prefix: common::BACKUP_PREFIX.get().cloned(),
+        basename_prefix: common::BACKUP_BASENAME_PREFIX.get().cloned(),
+        suffix: common::BACKUP_SUFFIX
+            .get()
+            .cloned()
+            .or_else(|| env::var("SIMPLE_BACKUP_SUFFIX").ok().filter(|s| !s.is_empty())),
+    }
+}
+
+/// 按 -V/-B/-Y/-z 的设置备份 file，返回备份文件名
+///
+/// 原文件被改名为备份文件，所以必须在新内容替换 file 之前调用；
+/// 无法改名（如 -B 指向另一个文件系统）时改为复制。file 不存在（补丁新建文件）时
+/// 与 GNU patch 一样留下一个空的备份文件作为占位。备份文件名同样要经过安全检查，
+/// 已经存在的同名符号链接不会被跟随。
+pub fn create_backup(file: &str) -> io::Result<String> {
+    let name = backupfile::find_backup_file_name(file, backup_type(), &backup_naming());
+
+    if let Some(dir) = Path::new(&name).parent()
+        && !dir.as_os_str().is_empty()
+        && !dir.is_dir()
+    {
+        safe::safe_mkdir(dir)?;
+    }
+    if fs::symlink_metadata(file).is_err() {
+        safe::safe_write(&name, b"")?;
+    } else if safe::safe_rename(file, &name).is_err() {
+        safe::safe_copy(file, &name)?;
+    }
+    Ok(name)
+}
+
+/// git 补丁中符号链接的权限
+pub const SYMLINK_MODE: u32 = 0o120000;
+
+/// git 补丁中的权限是否为普通文件（100644 或 100755）
+pub fn is_regular_mode(mode: u32) -> bool {
+    mode & 0o170000 == 0o100000
+}
+
+/// 文件权限对应的 git 普通文件权限：100755 或 100644
+pub fn git_mode(permissions: &fs::Permissions) -> u32 {
+    if permissions.mode() & 0o100 != 0 { 0o100755 } else { 0o100644 }
+}
+
+/// 文件在 git 中的权限：符号链接为 120000，普通文件为 100755 或 100644
+pub fn git_file_mode(file: &str) -> Option<u32> {
+    let metadata = fs::symlink_metadata(file).ok()?;
+    if metadata.file_type().is_symlink() {
+        Some(SYMLINK_MODE)
+    } else {
+        Some(git_mode(&metadata.permissions()))
+    }
+}
+
+/// 按 git 补丁中的普通文件权限设置执行权限
+///
+/// 与 git apply 一样只关心可执行位：可执行时给有读权限的用户加上执行权限，
+/// 否则去掉所有执行权限。新建的文件（current 为 None）从 umask 决定的默认权限开始。
+pub fn git_permissions(current: Option<&fs::Permissions>, mode: u32) -> fs::Permissions {
+    let base = current.map_or_else(|| 0o666 & !umask(), |p| p.mode() & 0o7777);
+    let mode = if mode & 0o111 != 0 { base | (base & 0o444) >> 2 } else { base & !0o111 };
+    fs::Permissions::from_mode(mode)
+}
+
+/// 当前进程的 umask，只能通过设置来读取，读完立即恢复
+fn umask() -> u32 {
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask as u32
+    }
+}
+
+/// 为要新建的 file 创建所在的目录
+///
+/// 先检查 file 本身：即使目录已经存在，也不能在工作目录以外新建文件。
+pub fn make_parent_dirs(file: &str) -> io::Result<()> {
+    let path = Path::new(file);
+    if !safe::is_allowed(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            tr!("refusing to create unsafe path: {}", file)
+        ));
+    }
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() && !dir.exists() => safe::safe_mkdir(dir),
+        _ => Ok(()),
+    }
+}
+
+/// 删除 file 所在的目录以及上级目录中已经变空的目录
+pub fn remove_empty_dirs(file: &str) {
+    let mut dir = Path::new(file).parent();
+    while let Some(d) = dir
+        && !d.as_os_str().is_empty()
+    {
+        // 目录不为空时删除失败，到此为止
+        if !d.is_dir() || safe::safe_unlink(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+/// 查找按当前备份设置为 file 生成过的备份文件
+pub fn find_backup(file: &str) -> Option<String> {
+    backupfile::find_existing_backup(file, backup_type(), &backup_naming())
 }
\ No newline at end of file
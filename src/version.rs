@@ -1,4 +1,6 @@
+use crate::i18n::tr;
+
 pub fn print_version() {
-    println!("GNU patch (Rust rewrite) v0.1.0");
-    println!("Copyright (c) 2025 Free Software Foundation, Inc.");
-}
\ No newline at end of file
+    println!("{}", tr!("GNU patch (Rust rewrite) v0.1.0"));
+    println!("{}", tr!("Copyright (C) 2025 Free Software Foundation, Inc."));
+}